@@ -0,0 +1,378 @@
+use std::fmt;
+
+use crate::error::CalcError;
+use crate::eval::Memory;
+use crate::library::Library;
+
+/// 式の構文木。column は 1 から数えた入力の中の位置で、評価時のエラーの表示に使う
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    /// mem の後のメモリ名
+    Memory(String),
+    Constant {
+        name: String,
+        column: usize,
+    },
+    Unary {
+        op: UnaryOp,
+        operand: Box<Expr>,
+    },
+    Binary {
+        op: BinaryOp,
+        /// 演算子の位置
+        column: usize,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Call {
+        name: String,
+        column: usize,
+        args: Vec<Expr>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Plus,
+    Minus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    // 0 の方向に切り捨てる整数除算
+    IntDivide,
+    Remainder,
+    Power,
+}
+
+impl UnaryOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            UnaryOp::Plus => "+",
+            UnaryOp::Minus => "-",
+        }
+    }
+}
+
+impl BinaryOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::IntDivide => "//",
+            BinaryOp::Remainder => "%",
+            BinaryOp::Power => "^",
+        }
+    }
+
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Add | BinaryOp::Subtract => ADDITIVE,
+            BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::IntDivide | BinaryOp::Remainder => {
+                MULTIPLICATIVE
+            }
+            BinaryOp::Power => POWER,
+        }
+    }
+}
+
+// 結合の強さ。大きいほど強く結び付く
+const ADDITIVE: u8 = 1;
+const MULTIPLICATIVE: u8 = 2;
+const UNARY: u8 = 3;
+const POWER: u8 = 4;
+const PRIMARY: u8 = 5;
+
+impl Expr {
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Binary { op, .. } => op.precedence(),
+            Expr::Unary { .. } => UNARY,
+            // 負の数は符号の付いた式として書く
+            Expr::Number(value) if value.is_sign_negative() => UNARY,
+            _ => PRIMARY,
+        }
+    }
+
+    /// 式を計算する
+    pub fn eval(&self, memory: &Memory, library: &Library) -> Result<f64, CalcError> {
+        match self {
+            Expr::Number(value) => Ok(*value),
+            Expr::Memory(name) => Ok(memory.get(name)),
+            Expr::Constant { name, column } => {
+                library
+                    .constant(name)
+                    .ok_or_else(|| CalcError::UnknownIdentifier {
+                        column: *column,
+                        name: name.clone(),
+                    })
+            }
+            Expr::Unary { op, operand } => {
+                let value = operand.eval(memory, library)?;
+                Ok(match op {
+                    UnaryOp::Plus => value,
+                    UnaryOp::Minus => -value,
+                })
+            }
+            Expr::Binary {
+                op,
+                column,
+                left,
+                right,
+            } => {
+                let left = left.eval(memory, library)?;
+                let right = right.eval(memory, library)?;
+                let divides = matches!(
+                    op,
+                    BinaryOp::Divide | BinaryOp::IntDivide | BinaryOp::Remainder
+                );
+                if divides && right == 0.0 {
+                    return Err(CalcError::DivisionByZero { column: *column });
+                }
+                Ok(match op {
+                    BinaryOp::Add => left + right,
+                    BinaryOp::Subtract => left - right,
+                    BinaryOp::Multiply => left * right,
+                    BinaryOp::Divide => left / right,
+                    // % の余りはこれと組になる
+                    BinaryOp::IntDivide => (left / right).trunc(),
+                    BinaryOp::Remainder => left % right,
+                    BinaryOp::Power => left.powf(right),
+                })
+            }
+            Expr::Call { name, column, args } => {
+                let Some((arity, function)) = library.function(name) else {
+                    return Err(CalcError::UnknownFunction {
+                        column: *column,
+                        name: name.clone(),
+                    });
+                };
+                if !arity.accepts(args.len()) {
+                    return Err(CalcError::ArityMismatch {
+                        column: *column,
+                        name: name.clone(),
+                        expected: arity,
+                        found: args.len(),
+                    });
+                }
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval(memory, library))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(function(&args))
+            }
+        }
+    }
+
+    /// 構文木を1行に1ノードずつ、枝を付けて表示する文字列
+    pub fn tree(&self) -> String {
+        let mut lines = Vec::new();
+        self.push_tree(&mut lines, "", "");
+        lines.join("\n")
+    }
+
+    // first は自分の行の前に、rest は子の行の前に付ける
+    fn push_tree(&self, lines: &mut Vec<String>, first: &str, rest: &str) {
+        let (label, children): (String, Vec<&Expr>) = match self {
+            Expr::Number(value) => (value.to_string(), vec![]),
+            Expr::Memory(name) => (format!("mem{}", name), vec![]),
+            Expr::Constant { name, .. } => (name.clone(), vec![]),
+            Expr::Unary { op, operand } => (op.symbol().to_string(), vec![operand]),
+            Expr::Binary {
+                op, left, right, ..
+            } => (op.symbol().to_string(), vec![left, right]),
+            Expr::Call { name, args, .. } => (format!("{}()", name), args.iter().collect()),
+        };
+        lines.push(format!("{}{}", first, label));
+        for (i, child) in children.iter().enumerate() {
+            if i + 1 == children.len() {
+                child.push_tree(lines, &format!("{}└─ ", rest), &format!("{}   ", rest));
+            } else {
+                child.push_tree(lines, &format!("{}├─ ", rest), &format!("{}│  ", rest));
+            }
+        }
+    }
+}
+
+/// 必要な括弧だけを付けて式を書く。読み直すと同じ構文木になる
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Number(value) => write!(f, "{}", value),
+            Expr::Memory(name) => write!(f, "mem{}", name),
+            Expr::Constant { name, .. } => write!(f, "{}", name),
+            Expr::Unary { op, operand } => {
+                write!(f, "{}", op.symbol())?;
+                write_operand(f, operand, operand.precedence() < UNARY)
+            }
+            Expr::Binary {
+                op: BinaryOp::Power,
+                left,
+                right,
+                ..
+            } => {
+                // 右結合で、底には数値や括弧しか書けない。指数には符号を付けられる
+                write_operand(f, left, left.precedence() < PRIMARY)?;
+                write!(f, "^")?;
+                write_operand(f, right, right.precedence() < UNARY)
+            }
+            Expr::Binary {
+                op, left, right, ..
+            } => {
+                // 左結合なので、同じ強さの演算は右側だけ括弧が要る
+                write_operand(f, left, left.precedence() < op.precedence())?;
+                write!(f, " {} ", op.symbol())?;
+                write_operand(f, right, right.precedence() <= op.precedence())
+            }
+            Expr::Call { name, args, .. } => {
+                write!(f, "{}(", name)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+fn write_operand(f: &mut fmt::Formatter, expr: &Expr, parenthesize: bool) -> fmt::Result {
+    if parenthesize {
+        write!(f, "({})", expr)
+    } else {
+        write!(f, "{}", expr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::token::tokenize;
+
+    fn parse_text(text: &str) -> Expr {
+        parse(&tokenize(text).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_display() {
+        let cases = [
+            ("1+2*3", "1 + 2 * 3"),
+            ("(1+2)*3", "(1 + 2) * 3"),
+            ("((1))", "1"),
+            ("(1*2)+3", "1 * 2 + 3"),
+            // 左結合なので、右側の同じ強さの演算だけ括弧が残る
+            ("(1-2)-3", "1 - 2 - 3"),
+            ("1-(2-3)", "1 - (2 - 3)"),
+            ("1+(2+3)", "1 + (2 + 3)"),
+            ("8/(4/2)", "8 / (4 / 2)"),
+            ("(7//2)%3", "7 // 2 % 3"),
+            // べき乗は右結合
+            ("2^(3^2)", "2^3^2"),
+            ("(2^3)^2", "(2^3)^2"),
+            ("-(2^2)", "-2^2"),
+            ("(-2)^2", "(-2)^2"),
+            ("2^(-1)", "2^-1"),
+            ("2^(1+1)", "2^(1 + 1)"),
+            ("-(1+2)", "-(1 + 2)"),
+            ("-(-2)", "--2"),
+            ("1 - (-2)", "1 - -2"),
+            ("(-2)*3", "-2 * 3"),
+            ("max( 1,(2) ,mem1*pi )", "max(1, 2, mem1 * pi)"),
+            ("sum()", "sum()"),
+            ("1.50e2", "150"),
+        ];
+        for (text, expected) in cases {
+            let expr = parse_text(text);
+            assert_eq!(expected, expr.to_string(), "{}", text);
+            // 書いた式を読み直すと同じ構文木になる
+            assert_eq!(
+                strip_columns(expr),
+                strip_columns(parse_text(expected)),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_display_extreme_numbers() {
+        for text in ["1e300", "1.7976931348623157e308", "5e-324", "1e-400"] {
+            let expr = parse_text(text);
+            // 書いた数値を読み直しても同じ値になる
+            assert_eq!(expr, parse_text(&expr.to_string()), "{}", text);
+        }
+    }
+
+    // 位置だけが違う構文木を比べるため、位置を消す
+    fn strip_columns(expr: Expr) -> Expr {
+        match expr {
+            Expr::Constant { name, .. } => Expr::Constant { name, column: 0 },
+            Expr::Unary { op, operand } => Expr::Unary {
+                op,
+                operand: Box::new(strip_columns(*operand)),
+            },
+            Expr::Binary {
+                op, left, right, ..
+            } => Expr::Binary {
+                op,
+                column: 0,
+                left: Box::new(strip_columns(*left)),
+                right: Box::new(strip_columns(*right)),
+            },
+            Expr::Call { name, args, .. } => Expr::Call {
+                name,
+                column: 0,
+                args: args.into_iter().map(strip_columns).collect(),
+            },
+            expr => expr,
+        }
+    }
+
+    #[test]
+    fn test_tree() {
+        let expected = "\
++
+├─ 1
+└─ *
+   ├─ -
+   │  └─ mem1
+   └─ max()
+      ├─ 2
+      └─ pi";
+        assert_eq!(expected, parse_text("1 + -mem1 * max(2, pi)").tree());
+    }
+
+    #[test]
+    fn test_eval_columns() {
+        let memory = Memory::new();
+        let library = Library::new();
+        let cases = [
+            ("1 + 2 // (1 - 1)", CalcError::DivisionByZero { column: 7 }),
+            (
+                "2 * x",
+                CalcError::UnknownIdentifier {
+                    column: 5,
+                    name: "x".to_string(),
+                },
+            ),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(
+                Err(expected),
+                parse_text(text).eval(&memory, &library),
+                "{}",
+                text
+            );
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use std::fmt;
+
+use crate::library::Arity;
+
+/// 式を読み取りまたは計算できなかった理由。column は 1 から数えた、原因の文字の位置
+#[derive(Debug, PartialEq)]
+pub enum CalcError {
+    /// 数値・演算子・括弧・名前のどれにもならない文字
+    InvalidCharacter {
+        column: usize,
+        found: char,
+    },
+    /// 大きすぎて f64 で表せない数値
+    NumberOutOfRange {
+        column: usize,
+    },
+    /// 定義されていない定数の名前
+    UnknownIdentifier {
+        column: usize,
+        name: String,
+    },
+    /// 定義されていない関数の呼び出し
+    UnknownFunction {
+        column: usize,
+        name: String,
+    },
+    /// 関数が受け取らない数の引数
+    ArityMismatch {
+        column: usize,
+        name: String,
+        expected: Arity,
+        found: usize,
+    },
+    /// その位置に書けないトークン。found が None なら式が途中で終わっている
+    UnexpectedToken {
+        column: usize,
+        found: Option<String>,
+    },
+    /// 閉じていない開き括弧、または対応する開き括弧の無い閉じ括弧
+    UnbalancedParen {
+        column: usize,
+    },
+    /// 式が終わった後に続く入力
+    TrailingInput {
+        column: usize,
+    },
+    DivisionByZero {
+        column: usize,
+    },
+}
+
+impl CalcError {
+    pub fn column(&self) -> usize {
+        match self {
+            CalcError::InvalidCharacter { column, .. }
+            | CalcError::UnknownIdentifier { column, .. }
+            | CalcError::UnknownFunction { column, .. }
+            | CalcError::ArityMismatch { column, .. }
+            | CalcError::UnexpectedToken { column, .. }
+            | CalcError::NumberOutOfRange { column }
+            | CalcError::UnbalancedParen { column }
+            | CalcError::TrailingInput { column }
+            | CalcError::DivisionByZero { column } => *column,
+        }
+    }
+
+    /// 入力の行の下に置いて、原因の文字の位置に ^ を合わせるための空白
+    pub fn caret_padding(&self, line: &str) -> String {
+        line.chars()
+            .take(self.column().saturating_sub(1))
+            .map(padding_under)
+            .collect()
+    }
+}
+
+// 文字の下に置く空白。タブはタブのまま、全角の文字には端末での幅に合わせて空白を 2 つ置く
+fn padding_under(c: char) -> &'static str {
+    match c {
+        '\t' => "\t",
+        _ if is_wide(c) => "  ",
+        _ => " ",
+    }
+}
+
+// 端末で 2 文字分の幅で表示される文字 (漢字・かな・ハングル・全角記号・絵文字など)
+fn is_wide(c: char) -> bool {
+    matches!(
+        c,
+        '\u{1100}'..='\u{115F}'
+            | '\u{2E80}'..='\u{303E}'
+            | '\u{3041}'..='\u{33FF}'
+            | '\u{3400}'..='\u{4DBF}'
+            | '\u{4E00}'..='\u{9FFF}'
+            | '\u{A000}'..='\u{A4CF}'
+            | '\u{AC00}'..='\u{D7A3}'
+            | '\u{F900}'..='\u{FAFF}'
+            | '\u{FE30}'..='\u{FE4F}'
+            | '\u{FF00}'..='\u{FF60}'
+            | '\u{FFE0}'..='\u{FFE6}'
+            | '\u{1F300}'..='\u{1F64F}'
+            | '\u{1F900}'..='\u{1F9FF}'
+            | '\u{20000}'..='\u{3FFFD}'
+    )
+}
+
+impl fmt::Display for CalcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CalcError::InvalidCharacter { found, .. } => {
+                write!(f, "'{}' は使えない文字です", found)
+            }
+            CalcError::NumberOutOfRange { .. } => write!(f, "数値が大きすぎます"),
+            CalcError::UnknownIdentifier { name, .. } => {
+                write!(f, "{} という名前はありません", name)
+            }
+            CalcError::UnknownFunction { name, .. } => {
+                write!(f, "{} という関数はありません", name)
+            }
+            CalcError::ArityMismatch {
+                name,
+                expected,
+                found,
+                ..
+            } => write!(
+                f,
+                "{} の引数は{}ですが、{}個指定されています",
+                name, expected, found
+            ),
+            CalcError::UnexpectedToken {
+                found: Some(found), ..
+            } => write!(f, "ここに '{}' は書けません", found),
+            CalcError::UnexpectedToken { found: None, .. } => {
+                write!(f, "式が途中で終わっています")
+            }
+            CalcError::UnbalancedParen { .. } => write!(f, "対応する括弧がありません"),
+            CalcError::TrailingInput { .. } => write!(f, "式の後に余計な入力があります"),
+            CalcError::DivisionByZero { .. } => write!(f, "0 で割ることはできません"),
+        }
+    }
+}
+
+impl std::error::Error for CalcError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_caret_padding() {
+        let cases = [
+            ("1 + x", 5, "    "),
+            ("x", 1, ""),
+            // 全角の文字の下には空白を 2 つ置く
+            ("１＋あ", 3, "    "),
+            ("漢字 + x", 6, "       "),
+            // タブはタブのまま残す
+            ("\t1 +\tx", 6, "\t   \t"),
+            ("\tあ\t@", 4, "\t  \t"),
+        ];
+        for (line, column, expected) in cases {
+            let error = CalcError::TrailingInput { column };
+            assert_eq!(expected, error.caret_padding(line), "{:?}", line);
+        }
+    }
+}
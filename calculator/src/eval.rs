@@ -0,0 +1,188 @@
+use std::collections::{hash_map::Entry, HashMap};
+
+use crate::error::CalcError;
+use crate::library::Library;
+use crate::parser;
+use crate::token::Spanned;
+
+/// 名前ごとの値を持つメモリ。無いメモリの値は 0
+#[derive(Default)]
+pub struct Memory {
+    slots: HashMap<String, f64>,
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Self {
+            slots: HashMap::new(),
+        }
+    }
+
+    /// メモリに値を足し、足した後の値を返す
+    pub fn add(&mut self, slot_name: String, prev_result: f64) -> f64 {
+        match self.slots.entry(slot_name) {
+            Entry::Occupied(mut entry) => {
+                // メモリが見つかったので値を更新
+                *entry.get_mut() += prev_result;
+                *entry.get()
+            }
+            Entry::Vacant(entry) => {
+                // メモリが見つからないので要素追加
+                entry.insert(prev_result);
+                prev_result
+            }
+        }
+    }
+
+    pub fn get(&self, slot_name: &str) -> f64 {
+        self.slots.get(slot_name).copied().unwrap_or(0.0)
+    }
+}
+
+/// トークン列の式を構文木にしてから計算する
+pub fn eval_expression(
+    tokens: &[Spanned],
+    memory: &Memory,
+    library: &Library,
+) -> Result<f64, CalcError> {
+    parser::parse(tokens)?.eval(memory, library)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::Arity;
+    use crate::token::tokenize;
+
+    fn eval(text: &str, memory: &Memory) -> Result<f64, CalcError> {
+        eval_expression(&tokenize(text)?, memory, &Library::new())
+    }
+
+    #[test]
+    fn test_eval_expression() {
+        let mut memory = Memory::new();
+        memory.add("1".to_string(), 4.0);
+        let cases = [
+            ("(1+2)*3", 9.0),
+            ("1 + 2 * 3", 7.0),
+            ("8/2/2", 2.0),
+            ("2*mem1", 8.0),
+            ("mem2 + 1", 1.0),
+            ("((1))", 1.0),
+            // 単項演算子
+            ("-3 * 2", -6.0),
+            ("-1--2*+3", 5.0),
+            ("2*-3", -6.0),
+            ("-(1+2)", -3.0),
+            ("--2", 2.0),
+            ("-mem1", -4.0),
+            // べき乗は右結合で、単項演算子や乗除算より先に計算する
+            ("2 ^ 10", 1024.0),
+            ("2^3^2", 512.0),
+            ("(2^3)^2", 64.0),
+            ("-2^2", -4.0),
+            ("(-2)^2", 4.0),
+            ("2^-1", 0.5),
+            ("3*2^2", 12.0),
+            ("2^2*3", 12.0),
+            // 剰余と整数除算は乗除算と同じ優先順位で左結合
+            ("7 % 3", 1.0),
+            ("-7 % 3", -1.0),
+            ("7.5 % 2", 1.5),
+            ("7 // 2", 3.0),
+            ("-7 // 2", -3.0),
+            ("20 // 3 // 2", 3.0),
+            ("20 % 7 % 4", 2.0),
+            ("1 + 7 // 2 * 2", 7.0),
+            ("2 * 7 % 4", 2.0),
+            ("10 - 4 - 3", 3.0),
+            // 関数と定数
+            ("sqrt(16)", 4.0),
+            ("sqrt(2)^2", 2.0000000000000004),
+            ("sin(pi/2)", 1.0),
+            ("log(8, 2)", 3.0),
+            ("log(1000)", 3.0),
+            ("max(1, 5, 3)", 5.0),
+            ("max(1,min(4,2)*3,5)", 6.0),
+            ("-abs(-2)^2", -4.0),
+            ("sum()", 0.0),
+            ("tau / pi", 2.0),
+            ("2*e/e", 2.0),
+            ("max(mem1, 3)", 4.0),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(Ok(expected), eval(text, &memory), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_eval_error() {
+        let memory = Memory::new();
+        let unexpected = |column, found: Option<&str>| CalcError::UnexpectedToken {
+            column,
+            found: found.map(str::to_string),
+        };
+        let cases = [
+            ("(1+2", CalcError::UnbalancedParen { column: 1 }),
+            ("1+2)", CalcError::UnbalancedParen { column: 4 }),
+            ("1 2", CalcError::TrailingInput { column: 3 }),
+            ("1 +", unexpected(4, None)),
+            ("* 3", unexpected(1, Some("*"))),
+            ("(1 2)", unexpected(4, Some("2"))),
+            ("()", unexpected(2, Some(")"))),
+            ("4/(2-2)", CalcError::DivisionByZero { column: 2 }),
+            ("4 % 0", CalcError::DivisionByZero { column: 3 }),
+            ("4 // 0", CalcError::DivisionByZero { column: 3 }),
+            ("2^", unexpected(3, None)),
+            ("2 ^ * 3", unexpected(5, Some("*"))),
+            ("-", unexpected(2, None)),
+            (
+                "foo+1",
+                CalcError::UnknownIdentifier {
+                    column: 1,
+                    name: "foo".to_string(),
+                },
+            ),
+            (
+                "1 + foo(2)",
+                CalcError::UnknownFunction {
+                    column: 5,
+                    name: "foo".to_string(),
+                },
+            ),
+            // 関数は呼び出さずに値としては使えない
+            (
+                "sqrt + 1",
+                CalcError::UnknownIdentifier {
+                    column: 1,
+                    name: "sqrt".to_string(),
+                },
+            ),
+            (
+                "2 * sqrt(4, 9)",
+                CalcError::ArityMismatch {
+                    column: 5,
+                    name: "sqrt".to_string(),
+                    expected: Arity::exactly(1),
+                    found: 2,
+                },
+            ),
+            (
+                "max()",
+                CalcError::ArityMismatch {
+                    column: 1,
+                    name: "max".to_string(),
+                    expected: Arity::at_least(1),
+                    found: 0,
+                },
+            ),
+            ("max(1, 2", CalcError::UnbalancedParen { column: 4 }),
+            ("max(1,)", unexpected(7, Some(")"))),
+            ("max(1 2)", unexpected(7, Some("2"))),
+            ("1, 2", CalcError::TrailingInput { column: 2 }),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(Err(expected), eval(text, &memory), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,21 @@
+//! 四則演算などの式を計算する電卓
+//!
+//! 式は parser::parse で構文木 (Expr) にしてから、Memory と Library を使って計算する。
+//! 組み込みの関数と定数に加えて、Library に Rust のクロージャを関数として登録できる
+
+pub mod ast;
+pub mod error;
+pub mod eval;
+pub mod library;
+pub mod parser;
+pub mod token;
+
+pub use ast::Expr;
+pub use error::CalcError;
+pub use eval::{eval_expression, Memory};
+pub use library::{Arity, Library};
+
+/// 1行の式を計算する
+pub fn evaluate(text: &str, memory: &Memory, library: &Library) -> Result<f64, CalcError> {
+    eval_expression(&token::tokenize(text)?, memory, library)
+}
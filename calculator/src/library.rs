@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::f64::consts;
+use std::fmt;
+
+/// 関数の本体。Arity に合わない数の引数で呼ぶと NaN を返す
+pub type Function = dyn Fn(&[f64]) -> f64;
+
+// 引数がひとつの組み込み関数
+type UnaryFunction = fn(f64) -> f64;
+
+/// 関数が受け取る引数の数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arity {
+    pub min: usize,
+    /// None なら上限なし
+    pub max: Option<usize>,
+}
+
+impl Arity {
+    pub const fn exactly(count: usize) -> Self {
+        Self {
+            min: count,
+            max: Some(count),
+        }
+    }
+
+    pub const fn at_least(min: usize) -> Self {
+        Self { min, max: None }
+    }
+
+    pub const fn between(min: usize, max: usize) -> Self {
+        Self {
+            min,
+            max: Some(max),
+        }
+    }
+
+    pub fn accepts(self, count: usize) -> bool {
+        self.min <= count && self.max.is_none_or(|max| count <= max)
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.max {
+            Some(max) if max == self.min => write!(f, "{}個", max),
+            Some(max) => write!(f, "{}〜{}個", self.min, max),
+            None => write!(f, "{}個以上", self.min),
+        }
+    }
+}
+
+/// 式から呼べる関数と定数
+///
+/// new() は組み込みの関数と定数 (pi, e, tau) を持つ。add_function で Rust のクロージャを追加できる
+pub struct Library {
+    functions: HashMap<String, (Arity, Box<Function>)>,
+    constants: HashMap<String, f64>,
+}
+
+impl Default for Library {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Library {
+    /// 関数も定数も無いライブラリ
+    pub fn empty() -> Self {
+        Self {
+            functions: HashMap::new(),
+            constants: HashMap::new(),
+        }
+    }
+
+    /// 組み込みの関数と定数を持つライブラリ
+    pub fn new() -> Self {
+        let mut library = Self::empty();
+
+        library.add_constant("pi", consts::PI);
+        library.add_constant("e", consts::E);
+        library.add_constant("tau", consts::TAU);
+
+        let unary: [(&str, UnaryFunction); 19] = [
+            ("sqrt", f64::sqrt),
+            ("cbrt", f64::cbrt),
+            ("abs", f64::abs),
+            ("exp", f64::exp),
+            ("ln", f64::ln),
+            ("sin", f64::sin),
+            ("cos", f64::cos),
+            ("tan", f64::tan),
+            ("asin", f64::asin),
+            ("acos", f64::acos),
+            ("atan", f64::atan),
+            ("sinh", f64::sinh),
+            ("cosh", f64::cosh),
+            ("tanh", f64::tanh),
+            ("floor", f64::floor),
+            ("ceil", f64::ceil),
+            ("round", f64::round),
+            ("trunc", f64::trunc),
+            ("signum", f64::signum),
+        ];
+        for (name, function) in unary {
+            library.add_function(name, Arity::exactly(1), move |args| function(args[0]));
+        }
+        library.add_function("atan2", Arity::exactly(2), |args| args[0].atan2(args[1]));
+        library.add_function("hypot", Arity::exactly(2), |args| args[0].hypot(args[1]));
+        library.add_function("pow", Arity::exactly(2), |args| args[0].powf(args[1]));
+        // log(x) は常用対数、log(x, base) は base を底とする対数
+        library.add_function("log", Arity::between(1, 2), |args| match args.get(1) {
+            Some(base) => args[0].log(*base),
+            None => args[0].log10(),
+        });
+        library.add_function("min", Arity::at_least(1), |args| {
+            args.iter().copied().fold(f64::INFINITY, f64::min)
+        });
+        library.add_function("max", Arity::at_least(1), |args| {
+            args.iter().copied().fold(f64::NEG_INFINITY, f64::max)
+        });
+        library.add_function("sum", Arity::at_least(0), |args| args.iter().sum());
+        library.add_function("avg", Arity::at_least(1), |args| {
+            args.iter().sum::<f64>() / args.len() as f64
+        });
+        library
+    }
+
+    /// 関数を追加する。同じ名前の関数があれば置き換える
+    ///
+    /// function には arity に合った数の引数だけが渡される。合わない数で呼ばれたら function を呼ばずに NaN を返す
+    ///
+    /// # Panics
+    ///
+    /// name が名前として書けない場合や、メモリとして読まれる mem で始まる場合
+    pub fn add_function(
+        &mut self,
+        name: &str,
+        arity: Arity,
+        function: impl Fn(&[f64]) -> f64 + 'static,
+    ) {
+        check_name(name);
+        let checked = move |args: &[f64]| {
+            if arity.accepts(args.len()) {
+                function(args)
+            } else {
+                f64::NAN
+            }
+        };
+        self.functions
+            .insert(name.to_string(), (arity, Box::new(checked)));
+    }
+
+    /// 定数を追加する。同じ名前の定数があれば置き換える
+    ///
+    /// # Panics
+    ///
+    /// add_function と同じ
+    pub fn add_constant(&mut self, name: &str, value: f64) {
+        check_name(name);
+        self.constants.insert(name.to_string(), value);
+    }
+
+    pub fn function(&self, name: &str) -> Option<(Arity, &Function)> {
+        self.functions
+            .get(name)
+            .map(|(arity, function)| (*arity, function.as_ref()))
+    }
+
+    pub fn constant(&self, name: &str) -> Option<f64> {
+        self.constants.get(name).copied()
+    }
+}
+
+fn check_name(name: &str) {
+    let mut chars = name.chars();
+    let valid = chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+        && !name.starts_with("mem");
+    assert!(valid, "関数や定数の名前に使えません: {}", name);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arity() {
+        let cases = [
+            (Arity::exactly(1), 0, false, "1個"),
+            (Arity::exactly(1), 1, true, "1個"),
+            (Arity::exactly(1), 2, false, "1個"),
+            (Arity::between(1, 2), 2, true, "1〜2個"),
+            (Arity::between(1, 2), 3, false, "1〜2個"),
+            (Arity::at_least(1), 0, false, "1個以上"),
+            (Arity::at_least(1), 10, true, "1個以上"),
+        ];
+        for (arity, count, accepts, text) in cases {
+            assert_eq!(accepts, arity.accepts(count), "{:?} {}", arity, count);
+            assert_eq!(text, arity.to_string());
+        }
+    }
+
+    #[test]
+    fn test_builtins() {
+        let library = Library::new();
+        let call = |name: &str, args: &[f64]| {
+            let (arity, function) = library.function(name).unwrap();
+            assert!(arity.accepts(args.len()), "{}", name);
+            function(args)
+        };
+
+        assert_eq!(Some(consts::PI), library.constant("pi"));
+        assert_eq!(None, library.constant("sqrt"));
+        assert!(library.function("pi").is_none());
+        assert_eq!(3.0, call("sqrt", &[9.0]));
+        assert_eq!(3.0, call("log", &[1000.0]));
+        assert_eq!(10.0, call("log", &[1024.0, 2.0]));
+        assert_eq!(5.0, call("max", &[1.0, 5.0, 3.0]));
+        assert_eq!(-1.0, call("min", &[1.0, -1.0]));
+        assert_eq!(0.0, call("sum", &[]));
+        assert_eq!(2.0, call("avg", &[1.0, 2.0, 3.0]));
+    }
+
+    #[test]
+    fn test_call_with_wrong_arity() {
+        let library = Library::new();
+        // 評価を通さずに呼んでも panic しない
+        for (name, args) in [("log", &[][..]), ("log", &[1.0, 2.0, 3.0]), ("sqrt", &[])] {
+            let (_, function) = library.function(name).unwrap();
+            assert!(function(args).is_nan(), "{} {:?}", name, args);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_add_function_memory_name() {
+        Library::empty().add_function("memo", Arity::exactly(0), |_| 0.0);
+    }
+}
@@ -1,9 +1,11 @@
-use std::collections::{hash_map::Entry, HashMap};
 use std::io::stdin;
-use std::mem;
+
+use calculator::token::{self, Token};
+use calculator::{eval_expression, parser, CalcError, Expr, Library, Memory};
 
 fn main() {
     let mut memory = Memory::new();
+    let library = Library::new();
     let mut prev_result: f64 = 0.0;
 
     for line in stdin().lines() {
@@ -13,11 +15,24 @@ fn main() {
             break;
         }
 
+        // : で始まる行は式を計算せずに表示するコマンド
+        if let Some(command) = line.strip_prefix(':') {
+            run_command(command);
+            continue;
+        }
+
         // トークン列に分割
-        let tokens = Token::split(&line);
+        let tokens = match token::tokenize(&line) {
+            Ok(tokens) if tokens.is_empty() => continue,
+            Ok(tokens) => tokens,
+            Err(error) => {
+                print_error(&line, &error);
+                continue;
+            }
+        };
 
         // 式の評価
-        match &tokens[0] {
+        match &tokens[0].token {
             Token::MemoryPlus(memory_name) => {
                 // メモリへの加算
                 let memory_name = memory_name.to_string();
@@ -31,190 +46,42 @@ fn main() {
                 print_output(result);
             }
             _ => {
-                // 式の値の計算
-                let result = eval_expression(&tokens, &memory);
-
-                print_output(result);
-                prev_result = result;
-            }
-        }
-    }
-
-    struct Memory {
-        slots: HashMap<String, f64>,
-    }
-
-    impl Memory {
-        fn new() -> Self {
-            Self {
-                slots: HashMap::new(),
-            }
-        }
-
-        fn add(&mut self, slot_name: String, prev_result: f64) -> f64 {
-            match self.slots.entry(slot_name) {
-                Entry::Occupied(mut entry) => {
-                    // メモリが見つかったので値を更新
-                    *entry.get_mut() += prev_result;
-                    *entry.get()
-                }
-                Entry::Vacant(entry) => {
-                    // メモリが見つからないので要素追加
-                    entry.insert(prev_result);
-                    prev_result
-                }
-            }
-        }
-
-        fn get(&self, slot_name: &str) -> f64 {
-            self.slots.get(slot_name).copied().unwrap_or(0.0)
-        }
-    }
-
-    #[derive(Debug, PartialEq)]
-    enum Token {
-        Number(f64),
-        MemoryRef(String),
-        MemoryPlus(String),
-        MemoryMinus(String),
-        Plus,
-        Minus,
-        Asterisk,
-        Slash,
-        LParen,
-        RParen,
-    }
-
-    impl Token {
-        fn parse(value: &str) -> Self {
-            match value {
-                "(" => Self::LParen,
-                ")" => Self::RParen,
-                "+" => Self::Plus,
-                "-" => Self::Minus,
-                "*" => Self::Asterisk,
-                "/" => Self::Slash,
-                _ if value.starts_with("mem") => {
-                    let mut memory_name = value[3..].to_string();
-                    if value.ends_with('+') {
-                        memory_name.pop();
-                        Self::MemoryPlus(memory_name)
-                    } else if value.ends_with('-') {
-                        memory_name.pop();
-                        Self::MemoryMinus(memory_name)
-                    } else {
-                        Self::MemoryRef(memory_name)
+                // 式の値の計算。エラーなら次の行へ進む
+                match eval_expression(&tokens, &memory, &library) {
+                    Ok(result) => {
+                        print_output(result);
+                        prev_result = result;
                     }
+                    Err(error) => print_error(&line, &error),
                 }
-                _ => Self::Number(value.parse().unwrap()),
             }
         }
-
-        fn split(text: &str) -> Vec<Self> {
-            text.split(char::is_whitespace).map(Self::parse).collect()
-        }
     }
+}
 
-    fn eval_token(token: &Token, memory: &Memory) -> f64 {
-        match token {
-            Token::Number(value) => {
-                // 数値の場合はそのまま返す
-                *value
-            }
-            Token::MemoryRef(memory_name) => {
-                // メモリ参照の場合はメモリから値を取得
-                memory.get(memory_name)
-            }
-            _ => {
-                // それ以外の場合はエラー
-                unreachable!()
-            }
-        }
-    }
-
-    fn eval_expression(tokens: &[Token], memory: &Memory) -> f64 {
-        let (result, index) = eval_additive_expression(tokens, 0, memory);
-        // 正しく計算で切れていればトークン列の最後に到達しているはず
-        assert_eq!(tokens.len(), index);
-        result
-    }
-
-    fn eval_additive_expression(tokens: &[Token], index: usize, memory: &Memory) -> (f64, usize) {
-        let mut index = index;
-        let mut result;
-
-        (result, index) = eval_multiplicative_expression(tokens, index, memory);
-
-        while index < tokens.len() {
-            match &tokens[index] {
-                Token::Plus => {
-                    let (value, next) = eval_multiplicative_expression(tokens, index + 1, memory);
-
-                    result += value;
-                    index = next;
-                }
-                Token::Minus => {
-                    let (value, next) = eval_multiplicative_expression(tokens, index + 1, memory);
-                    result -= value;
-                    index = next;
-                }
-                _ => break,
-            }
-        }
-        (result, index)
-    }
-
-    fn eval_multiplicative_expression(
-        tokens: &[Token],
-        index: usize,
-        memory: &Memory,
-    ) -> (f64, usize) {
-        let mut index = index;
-        let mut result;
-
-        (result, index) = eval_primary_expression(tokens, index, memory);
-
-        while index < tokens.len() {
-            match &tokens[index] {
-                Token::Asterisk => {
-                    let (value, next) = eval_primary_expression(tokens, index + 1, memory);
-                    result *= value;
-                    index = next;
-                }
-                Token::Slash => {
-                    let (value, next) = eval_primary_expression(tokens, index + 1, memory);
-                    result /= value;
-                    index = next;
-                }
-                _ => break,
-            }
+// :ast は構文木を、:fmt は必要な括弧だけを付けた式を表示する
+fn run_command(command: &str) {
+    let (name, text) = command.split_once(' ').unwrap_or((command, ""));
+    let show: fn(&Expr) -> String = match name {
+        "ast" => Expr::tree,
+        "fmt" => Expr::to_string,
+        _ => {
+            eprintln!("エラー: 不明なコマンドです: :{}", name);
+            return;
         }
-        (result, index)
+    };
+    match token::tokenize(text).and_then(|tokens| parser::parse(&tokens)) {
+        Ok(expr) => println!("{}", show(&expr)),
+        // 位置は式の部分の中で数える
+        Err(error) => print_error(text, &error),
     }
+}
 
-    fn eval_primary_expression(tokens: &[Token], index: usize, memory: &Memory) -> (f64, usize) {
-        let first_token = &tokens[index];
-
-        match first_token {
-            Token::LParen => {
-                // 開き括弧で始まっているので、括弧内の式を評価
-                let (result, next) = eval_additive_expression(tokens, index + 1, memory);
-                assert_eq!(Token::RParen, tokens[next]);
-
-                (result, next + 1)
-            }
-            Token::Number(value) => {
-                // 数値なのでその値と次の位置を返す
-                (*value, index + 1)
-            }
-            Token::MemoryRef(memory_name) => (memory.get(memory_name), index + 1),
-
-            _ => {
-                // それ以外の場合はエラー
-                unreachable!()
-            }
-        }
-    }
+// 入力の下に、エラーの位置を指す ^ を表示する
+fn print_error(line: &str, error: &CalcError) {
+    eprintln!("{}", line);
+    eprintln!("{}^", error.caret_padding(line));
+    eprintln!("エラー: {}", error);
 }
 
 fn print_output(value: f64) {
@@ -0,0 +1,219 @@
+use crate::ast::{BinaryOp, Expr, UnaryOp};
+use crate::error::CalcError;
+use crate::token::{Spanned, Token};
+
+/// トークン列を構文木にする。名前が定義されているかは評価するときに調べる
+pub fn parse(tokens: &[Spanned]) -> Result<Expr, CalcError> {
+    let (expr, index) = parse_additive_expression(tokens, 0)?;
+    // 正しく読めていればトークン列の最後に到達しているはず
+    match tokens.get(index) {
+        None => Ok(expr),
+        Some(Spanned {
+            token: Token::RParen,
+            column,
+            ..
+        }) => Err(CalcError::UnbalancedParen { column: *column }),
+        Some(spanned) => Err(CalcError::TrailingInput {
+            column: spanned.column,
+        }),
+    }
+}
+
+fn parse_additive_expression(tokens: &[Spanned], index: usize) -> Result<(Expr, usize), CalcError> {
+    let mut index = index;
+    let mut expr;
+
+    (expr, index) = parse_multiplicative_expression(tokens, index)?;
+
+    while index < tokens.len() {
+        let op = match &tokens[index].token {
+            Token::Plus => BinaryOp::Add,
+            Token::Minus => BinaryOp::Subtract,
+            _ => break,
+        };
+        let (right, next) = parse_multiplicative_expression(tokens, index + 1)?;
+        expr = binary(op, &tokens[index], expr, right);
+        index = next;
+    }
+    Ok((expr, index))
+}
+
+fn parse_multiplicative_expression(
+    tokens: &[Spanned],
+    index: usize,
+) -> Result<(Expr, usize), CalcError> {
+    let mut index = index;
+    let mut expr;
+
+    (expr, index) = parse_unary_expression(tokens, index)?;
+
+    while index < tokens.len() {
+        let op = match &tokens[index].token {
+            Token::Asterisk => BinaryOp::Multiply,
+            Token::Slash => BinaryOp::Divide,
+            Token::DoubleSlash => BinaryOp::IntDivide,
+            Token::Percent => BinaryOp::Remainder,
+            _ => break,
+        };
+        let (right, next) = parse_unary_expression(tokens, index + 1)?;
+        expr = binary(op, &tokens[index], expr, right);
+        index = next;
+    }
+    Ok((expr, index))
+}
+
+fn parse_unary_expression(tokens: &[Spanned], index: usize) -> Result<(Expr, usize), CalcError> {
+    let op = match tokens.get(index).map(|spanned| &spanned.token) {
+        Some(Token::Plus) => UnaryOp::Plus,
+        Some(Token::Minus) => UnaryOp::Minus,
+        _ => return parse_power_expression(tokens, index),
+    };
+    let (operand, next) = parse_unary_expression(tokens, index + 1)?;
+    Ok((
+        Expr::Unary {
+            op,
+            operand: Box::new(operand),
+        },
+        next,
+    ))
+}
+
+// ^ は右結合で、単項演算子より優先する。2^3^2 は 2^(3^2)、-2^2 は -(2^2)
+fn parse_power_expression(tokens: &[Spanned], index: usize) -> Result<(Expr, usize), CalcError> {
+    let (base, index) = parse_primary_expression(tokens, index)?;
+    match tokens.get(index) {
+        Some(
+            caret @ Spanned {
+                token: Token::Caret,
+                ..
+            },
+        ) => {
+            // 指数には符号を付けられる (2^-1)
+            let (exponent, next) = parse_unary_expression(tokens, index + 1)?;
+            Ok((binary(BinaryOp::Power, caret, base, exponent), next))
+        }
+        _ => Ok((base, index)),
+    }
+}
+
+fn parse_primary_expression(tokens: &[Spanned], index: usize) -> Result<(Expr, usize), CalcError> {
+    let Some(first_token) = tokens.get(index) else {
+        // 値が来るはずのところで式が終わっている
+        return Err(CalcError::UnexpectedToken {
+            column: end_column(tokens),
+            found: None,
+        });
+    };
+
+    match &first_token.token {
+        Token::LParen => {
+            // 開き括弧で始まっているので、括弧内の式を読む
+            let (expr, next) = parse_additive_expression(tokens, index + 1)?;
+            match tokens.get(next) {
+                Some(Spanned {
+                    token: Token::RParen,
+                    ..
+                }) => Ok((expr, next + 1)),
+                // 閉じ括弧が無いまま式が終わった
+                None => Err(CalcError::UnbalancedParen {
+                    column: first_token.column,
+                }),
+                Some(spanned) => Err(unexpected(spanned)),
+            }
+        }
+        Token::Number(value) => {
+            // 数値なのでその値と次の位置を返す
+            Ok((Expr::Number(*value), index + 1))
+        }
+        Token::MemoryRef(memory_name) => Ok((Expr::Memory(memory_name.clone()), index + 1)),
+        Token::Identifier(name) => match tokens.get(index + 1) {
+            Some(Spanned {
+                token: Token::LParen,
+                ..
+            }) => parse_function_call(tokens, index),
+            _ => Ok((
+                Expr::Constant {
+                    name: name.clone(),
+                    column: first_token.column,
+                },
+                index + 1,
+            )),
+        },
+
+        _ => {
+            // それ以外の場合はエラー
+            Err(unexpected(first_token))
+        }
+    }
+}
+
+// 名前 ( 引数, ... ) の形の関数呼び出し。index は名前の位置
+fn parse_function_call(tokens: &[Spanned], index: usize) -> Result<(Expr, usize), CalcError> {
+    let name_token = &tokens[index];
+    let paren = &tokens[index + 1];
+
+    let mut args = Vec::new();
+    let mut index = index + 2;
+    // 引数の無い呼び出し
+    if let Some(Spanned {
+        token: Token::RParen,
+        ..
+    }) = tokens.get(index)
+    {
+        index += 1;
+    } else {
+        loop {
+            let (arg, next) = parse_additive_expression(tokens, index)?;
+            args.push(arg);
+            match tokens.get(next) {
+                Some(Spanned {
+                    token: Token::Comma,
+                    ..
+                }) => index = next + 1,
+                Some(Spanned {
+                    token: Token::RParen,
+                    ..
+                }) => {
+                    index = next + 1;
+                    break;
+                }
+                None => {
+                    return Err(CalcError::UnbalancedParen {
+                        column: paren.column,
+                    })
+                }
+                Some(spanned) => return Err(unexpected(spanned)),
+            }
+        }
+    }
+
+    let call = Expr::Call {
+        name: name_token.token.to_string(),
+        column: name_token.column,
+        args,
+    };
+    Ok((call, index))
+}
+
+fn binary(op: BinaryOp, operator: &Spanned, left: Expr, right: Expr) -> Expr {
+    Expr::Binary {
+        op,
+        column: operator.column,
+        left: Box::new(left),
+        right: Box::new(right),
+    }
+}
+
+fn unexpected(spanned: &Spanned) -> CalcError {
+    CalcError::UnexpectedToken {
+        column: spanned.column,
+        found: Some(spanned.token.to_string()),
+    }
+}
+
+// 最後のトークンの次の位置
+fn end_column(tokens: &[Spanned]) -> usize {
+    tokens
+        .last()
+        .map_or(1, |spanned| spanned.column + spanned.width)
+}
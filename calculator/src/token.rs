@@ -0,0 +1,363 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use crate::error::CalcError;
+
+#[derive(Debug, PartialEq)]
+pub enum Token {
+    Number(f64),
+    MemoryRef(String),
+    MemoryPlus(String),
+    MemoryMinus(String),
+    // 関数や定数の名前
+    Identifier(String),
+    Plus,
+    Minus,
+    Asterisk,
+    Slash,
+    // 整数除算 (//)
+    DoubleSlash,
+    Percent,
+    Caret,
+    LParen,
+    RParen,
+    Comma,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "{}", value),
+            Token::MemoryRef(name) => write!(f, "mem{}", name),
+            Token::MemoryPlus(name) => write!(f, "mem{}+", name),
+            Token::MemoryMinus(name) => write!(f, "mem{}-", name),
+            Token::Identifier(name) => write!(f, "{}", name),
+            Token::Plus => write!(f, "+"),
+            Token::Minus => write!(f, "-"),
+            Token::Asterisk => write!(f, "*"),
+            Token::Slash => write!(f, "/"),
+            Token::DoubleSlash => write!(f, "//"),
+            Token::Percent => write!(f, "%"),
+            Token::Caret => write!(f, "^"),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+            Token::Comma => write!(f, ","),
+        }
+    }
+}
+
+/// 入力の中での位置が付いたトークン
+#[derive(Debug, PartialEq)]
+pub struct Spanned {
+    pub token: Token,
+    /// 1 から数えた、最初の文字の位置
+    pub column: usize,
+    /// 文字数
+    pub width: usize,
+}
+
+/// 1文字ずつ読んでトークン列に分ける。トークンの間の空白は無くてもよい
+pub fn tokenize(text: &str) -> Result<Vec<Spanned>, CalcError> {
+    let mut lexer = Lexer {
+        text,
+        chars: text.char_indices().peekable(),
+        column: 0,
+    };
+    let mut tokens = Vec::new();
+
+    while let Some(c) = lexer.peek() {
+        if c.is_whitespace() {
+            lexer.next();
+            continue;
+        }
+        let column = lexer.column + 1;
+        let token = match c {
+            // 符号は数値に含めず、単項演算子として評価する (-2^2 は -(2^2))
+            '0'..='9' | '.' => lexer.number()?,
+            _ if c.is_alphabetic() || c == '_' => lexer.identifier(),
+            _ => {
+                lexer.next();
+                match c {
+                    '+' => Token::Plus,
+                    '-' => Token::Minus,
+                    '*' => Token::Asterisk,
+                    '/' if lexer.next_if(|c| c == '/').is_some() => Token::DoubleSlash,
+                    '/' => Token::Slash,
+                    '%' => Token::Percent,
+                    '^' => Token::Caret,
+                    '(' => Token::LParen,
+                    ')' => Token::RParen,
+                    ',' => Token::Comma,
+                    found => return Err(CalcError::InvalidCharacter { column, found }),
+                }
+            }
+        };
+        tokens.push(Spanned {
+            token,
+            column,
+            width: lexer.column + 1 - column,
+        });
+    }
+    Ok(tokens)
+}
+
+struct Lexer<'a> {
+    text: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    // 読み終えた文字数
+    column: usize,
+}
+
+impl Lexer<'_> {
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|&(_, c)| c)
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let (_, c) = self.chars.next()?;
+        self.column += 1;
+        Some(c)
+    }
+
+    fn next_if(&mut self, predicate: impl Fn(char) -> bool) -> Option<char> {
+        match self.peek() {
+            Some(c) if predicate(c) => self.next(),
+            _ => None,
+        }
+    }
+
+    // まだ読んでいない部分
+    fn rest(&mut self) -> &str {
+        let offset = self.chars.peek().map_or(self.text.len(), |&(i, _)| i);
+        &self.text[offset..]
+    }
+
+    // 123, 1.5, .5, 1e-3 のような数値
+    fn number(&mut self) -> Result<Token, CalcError> {
+        let mut literal = String::new();
+        let start = self.column + 1;
+        let mut digits = 0;
+        while let Some(c) = self.next_if(|c| c.is_ascii_digit()) {
+            literal.push(c);
+            digits += 1;
+        }
+        if let Some(c) = self.next_if(|c| c == '.') {
+            literal.push(c);
+            while let Some(c) = self.next_if(|c| c.is_ascii_digit()) {
+                literal.push(c);
+                digits += 1;
+            }
+        }
+        if digits == 0 {
+            return Err(CalcError::InvalidCharacter {
+                column: start,
+                found: '.',
+            });
+        }
+        // 指数は e の後に (符号と) 数字が続くときだけ。続かなければ e は数値の外
+        if self.exponent_follows() {
+            literal.extend(self.next());
+            literal.extend(self.next_if(|c| c == '+' || c == '-'));
+            while let Some(c) = self.next_if(|c| c.is_ascii_digit()) {
+                literal.push(c);
+            }
+        }
+        // 数字と小数点と指数だけからなるので、必ず解釈できる
+        let value: f64 = literal.parse().unwrap();
+        // 1e400 のように無限大になる数値は、書き直しても同じ式として読めないので受け付けない
+        if !value.is_finite() {
+            return Err(CalcError::NumberOutOfRange { column: start });
+        }
+        Ok(Token::Number(value))
+    }
+
+    fn exponent_follows(&mut self) -> bool {
+        let mut rest = self.rest().chars();
+        if !matches!(rest.next(), Some('e' | 'E')) {
+            return false;
+        }
+        match rest.next() {
+            Some('+' | '-') => rest.next().is_some_and(|c| c.is_ascii_digit()),
+            Some(c) => c.is_ascii_digit(),
+            None => false,
+        }
+    }
+
+    // mem で始まる名前はメモリ。mem の後がメモリ名で、直後の + や - で行が終わればメモリへの加算・減算
+    // それ以外の名前が関数や定数としてあるかは、評価するときに調べる
+    fn identifier(&mut self) -> Token {
+        let mut identifier = String::new();
+        while let Some(c) = self.next_if(|c| c.is_alphanumeric() || c == '_') {
+            identifier.push(c);
+        }
+        let Some(name) = identifier.strip_prefix("mem") else {
+            return Token::Identifier(identifier);
+        };
+        let name = name.to_string();
+        let rest = self.rest();
+        let sign = rest.chars().next();
+        if matches!(sign, Some('+' | '-')) && rest[1..].trim().is_empty() {
+            self.next();
+            if sign == Some('+') {
+                Token::MemoryPlus(name)
+            } else {
+                Token::MemoryMinus(name)
+            }
+        } else {
+            Token::MemoryRef(name)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        let cases = [
+            (
+                "(1+2)*3",
+                vec![
+                    Token::LParen,
+                    Token::Number(1.0),
+                    Token::Plus,
+                    Token::Number(2.0),
+                    Token::RParen,
+                    Token::Asterisk,
+                    Token::Number(3.0),
+                ],
+            ),
+            // 空白で区切った以前の書き方もそのまま使える
+            (
+                "( 1 + 2 ) * 3",
+                vec![
+                    Token::LParen,
+                    Token::Number(1.0),
+                    Token::Plus,
+                    Token::Number(2.0),
+                    Token::RParen,
+                    Token::Asterisk,
+                    Token::Number(3.0),
+                ],
+            ),
+            (
+                "2*mem1",
+                vec![
+                    Token::Number(2.0),
+                    Token::Asterisk,
+                    Token::MemoryRef("1".to_string()),
+                ],
+            ),
+            (
+                "1.5e3/.5-2E-2",
+                vec![
+                    Token::Number(1500.0),
+                    Token::Slash,
+                    Token::Number(0.5),
+                    Token::Minus,
+                    Token::Number(0.02),
+                ],
+            ),
+            // 符号はどこでも演算子のトークン
+            (
+                "-1--2*+3",
+                vec![
+                    Token::Minus,
+                    Token::Number(1.0),
+                    Token::Minus,
+                    Token::Minus,
+                    Token::Number(2.0),
+                    Token::Asterisk,
+                    Token::Plus,
+                    Token::Number(3.0),
+                ],
+            ),
+            (
+                "7//2%3^2",
+                vec![
+                    Token::Number(7.0),
+                    Token::DoubleSlash,
+                    Token::Number(2.0),
+                    Token::Percent,
+                    Token::Number(3.0),
+                    Token::Caret,
+                    Token::Number(2.0),
+                ],
+            ),
+            ("mem1+", vec![Token::MemoryPlus("1".to_string())]),
+            ("memx- ", vec![Token::MemoryMinus("x".to_string())]),
+            (
+                "mem1-2",
+                vec![
+                    Token::MemoryRef("1".to_string()),
+                    Token::Minus,
+                    Token::Number(2.0),
+                ],
+            ),
+            (
+                "log(x,2)+e",
+                vec![
+                    Token::Identifier("log".to_string()),
+                    Token::LParen,
+                    Token::Identifier("x".to_string()),
+                    Token::Comma,
+                    Token::Number(2.0),
+                    Token::RParen,
+                    Token::Plus,
+                    Token::Identifier("e".to_string()),
+                ],
+            ),
+            // 数字の続かない e は指数ではなく名前
+            (
+                "2e",
+                vec![Token::Number(2.0), Token::Identifier("e".to_string())],
+            ),
+            ("", vec![]),
+        ];
+        for (text, expected) in cases {
+            let tokens: Vec<_> = tokenize(text)
+                .unwrap()
+                .into_iter()
+                .map(|spanned| spanned.token)
+                .collect();
+            assert_eq!(expected, tokens, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_tokenize_columns() {
+        let columns: Vec<_> = tokenize(" 12.5*(mem1 -3)")
+            .unwrap()
+            .into_iter()
+            .map(|spanned| (spanned.column, spanned.width))
+            .collect();
+        assert_eq!(
+            vec![(2, 4), (6, 1), (7, 1), (8, 4), (13, 1), (14, 1), (15, 1)],
+            columns
+        );
+    }
+
+    #[test]
+    fn test_tokenize_error() {
+        let cases = [
+            ("(1+2)#3", 6, '#'),
+            ("1 + . ", 5, '.'),
+            ("１+2", 1, '１'),
+            ("max(1;2)", 6, ';'),
+        ];
+        for (text, column, found) in cases {
+            assert_eq!(
+                Err(CalcError::InvalidCharacter { column, found }),
+                tokenize(text),
+                "{}",
+                text
+            );
+        }
+        assert_eq!(
+            Err(CalcError::NumberOutOfRange { column: 3 }),
+            tokenize("2*1e400")
+        );
+    }
+}
@@ -0,0 +1,57 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use calculator::{evaluate, Arity, CalcError, Library, Memory};
+
+#[test]
+fn test_add_function() {
+    let mut library = Library::new();
+    library.add_function("double", Arity::exactly(1), |args| args[0] * 2.0);
+    library.add_function("clamp", Arity::exactly(3), |args| {
+        args[0].clamp(args[1], args[2])
+    });
+    library.add_constant("answer", 42.0);
+    // クロージャは外の値を持てる
+    let calls = Rc::new(Cell::new(0));
+    let counter = Rc::clone(&calls);
+    library.add_function("count", Arity::at_least(0), move |args| {
+        counter.set(counter.get() + 1);
+        args.len() as f64
+    });
+    let memory = Memory::new();
+
+    let cases = [
+        ("double(21)", 42.0),
+        ("clamp(answer, 0, 10)", 10.0),
+        ("count(1, 2, 3) + count()", 3.0),
+        ("sqrt(double(8))", 4.0),
+    ];
+    for (text, expected) in cases {
+        assert_eq!(Ok(expected), evaluate(text, &memory, &library), "{}", text);
+    }
+    assert_eq!(2, calls.get());
+    assert_eq!(
+        Err(CalcError::ArityMismatch {
+            column: 1,
+            name: "double".to_string(),
+            expected: Arity::exactly(1),
+            found: 0,
+        }),
+        evaluate("double()", &memory, &library)
+    );
+}
+
+#[test]
+fn test_empty_library() {
+    let library = Library::empty();
+    let memory = Memory::new();
+
+    assert_eq!(Ok(3.0), evaluate("1 + 2", &memory, &library));
+    assert_eq!(
+        Err(CalcError::UnknownIdentifier {
+            column: 1,
+            name: "pi".to_string(),
+        }),
+        evaluate("pi", &memory, &library)
+    );
+}
@@ -0,0 +1,306 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OpenFlags};
+
+use crate::{read_calendar, save_calendar, storage, Calendar, MyError, Schedule};
+
+/// カレンダーを保存する形式。ファイルの拡張子で見分ける
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum BackendKind {
+    /// カレンダー全体をひとつの JSON ファイルに書く
+    #[default]
+    Json,
+    /// 予定ごとに SQLite の行として書く
+    Sqlite,
+}
+
+impl BackendKind {
+    pub fn extension(self) -> &'static str {
+        match self {
+            BackendKind::Json => "json",
+            BackendKind::Sqlite => "db",
+        }
+    }
+
+    pub fn of(path: &Path) -> Self {
+        if path
+            .extension()
+            .is_some_and(|extension| extension == BackendKind::Sqlite.extension())
+        {
+            BackendKind::Sqlite
+        } else {
+            BackendKind::Json
+        }
+    }
+}
+
+/// 保存するときに、読み込んだ後で何が変わったか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    /// この ID の予定を追加または変更した
+    Put(u64),
+    Delete(u64),
+    /// カレンダー全体を書き直す
+    All,
+}
+
+/// カレンダーの読み書き
+///
+/// save には load か load_range で読んだカレンダーを変更して渡す
+pub trait Backend {
+    fn load(&self) -> Result<Calendar, MyError>;
+    /// [from, to) に発生がありうる予定を読む。絞り込めない形式は全部を返す
+    fn load_range(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Calendar, MyError>;
+    fn save(&self, calendar: &Calendar, change: Change) -> Result<(), MyError>;
+}
+
+/// 拡張子に合った形式でカレンダーのファイルを開く
+pub fn open(path: &Path) -> Box<dyn Backend> {
+    let path = path.to_path_buf();
+    match BackendKind::of(&path) {
+        BackendKind::Json => Box::new(JsonBackend { path }),
+        BackendKind::Sqlite => Box::new(SqliteBackend { path }),
+    }
+}
+
+/// カレンダーとして読めるファイルかどうか。ファイルは変更しない
+///
+/// データディレクトリにある、カレンダーではない JSON や SQLite のファイルを見分けるのに使う
+pub fn is_calendar(path: &Path) -> bool {
+    match BackendKind::of(path) {
+        BackendKind::Json => fs::read_to_string(path)
+            .is_ok_and(|text| serde_json::from_str::<Calendar>(&text).is_ok()),
+        // 読み取り専用で開き、予定のテーブルがあるかだけを見る
+        BackendKind::Sqlite => Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .and_then(|connection| {
+                connection.query_row(
+                    "SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = 'schedules'",
+                    [],
+                    |row| row.get::<_, i64>(0),
+                )
+            })
+            .is_ok_and(|count| count > 0),
+    }
+}
+
+/// 変更のたびにファイル全体を書き直す。以前のファイルはバックアップに残る
+pub struct JsonBackend {
+    path: PathBuf,
+}
+
+impl Backend for JsonBackend {
+    fn load(&self) -> Result<Calendar, MyError> {
+        read_calendar(&self.path)
+    }
+
+    // ファイル全体を読むしかないので、絞り込みは呼び出し側に任せる
+    fn load_range(&self, _from: DateTime<Utc>, _to: DateTime<Utc>) -> Result<Calendar, MyError> {
+        self.load()
+    }
+
+    fn save(&self, calendar: &Calendar, _change: Change) -> Result<(), MyError> {
+        save_calendar(&self.path, calendar)
+    }
+}
+
+/// 予定を行として持ち、開始と最後の終了の索引で期間を絞り込む
+///
+/// 予定の中身は JSON のまま data 列に入れるので、項目を増やしてもテーブルは変わらない
+pub struct SqliteBackend {
+    path: PathBuf,
+}
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS schedules (
+        id INTEGER PRIMARY KEY,
+        start INTEGER NOT NULL,
+        last_end INTEGER,
+        data TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS schedules_start ON schedules (start);
+    CREATE INDEX IF NOT EXISTS schedules_last_end ON schedules (last_end);
+    CREATE TABLE IF NOT EXISTS meta (
+        key TEXT PRIMARY KEY,
+        value INTEGER NOT NULL
+    );
+";
+
+impl SqliteBackend {
+    fn connect(&self) -> Result<Connection, MyError> {
+        storage::create_parent_dir(&self.path).map_err(|source| MyError::File {
+            path: self.path.clone(),
+            source,
+        })?;
+        let connection = Connection::open(&self.path)?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(connection)
+    }
+
+    fn query(&self, condition: &str, params: impl rusqlite::Params) -> Result<Calendar, MyError> {
+        // JSON と同じく、まだファイルが無ければ空のカレンダーとして扱う
+        if !self.path.exists() {
+            return Ok(Calendar::default());
+        }
+        let connection = self.connect()?;
+        let mut statement = connection.prepare(&format!(
+            "SELECT data FROM schedules WHERE {} ORDER BY id",
+            condition
+        ))?;
+        let schedules = statement
+            .query_map(params, |row| row.get::<_, String>(0))?
+            .map(|data| Ok(serde_json::from_str(&data?)?))
+            .collect::<Result<_, MyError>>()?;
+        let next_id: i64 = connection
+            .query_row("SELECT value FROM meta WHERE key = 'next_id'", [], |row| {
+                row.get(0)
+            })
+            .or_else(|error| match error {
+                rusqlite::Error::QueryReturnedNoRows => Ok(0),
+                error => Err(error),
+            })?;
+        Ok(Calendar {
+            schedules,
+            next_id: next_id as u64,
+        })
+    }
+}
+
+impl Backend for SqliteBackend {
+    fn load(&self) -> Result<Calendar, MyError> {
+        self.query("1", [])
+    }
+
+    fn load_range(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Calendar, MyError> {
+        self.query(
+            "start < ?1 AND (last_end IS NULL OR last_end > ?2)",
+            params![to.timestamp(), from.timestamp()],
+        )
+    }
+
+    fn save(&self, calendar: &Calendar, change: Change) -> Result<(), MyError> {
+        // 変更はひとつのトランザクションで書くので、途中で落ちても前の状態に戻る
+        let mut connection = self.connect()?;
+        let transaction = connection.transaction()?;
+        match change {
+            Change::Put(id) => {
+                if let Some(schedule) = calendar.schedules.iter().find(|s| s.id == id) {
+                    put(&transaction, schedule)?;
+                }
+            }
+            Change::Delete(id) => {
+                transaction.execute("DELETE FROM schedules WHERE id = ?1", [id as i64])?;
+            }
+            Change::All => {
+                transaction.execute("DELETE FROM schedules", [])?;
+                for schedule in &calendar.schedules {
+                    put(&transaction, schedule)?;
+                }
+            }
+        }
+        transaction.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES ('next_id', ?1)",
+            [calendar.next_id as i64],
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+fn put(connection: &Connection, schedule: &Schedule) -> Result<(), MyError> {
+    connection.execute(
+        "INSERT OR REPLACE INTO schedules (id, start, last_end, data) VALUES (?1, ?2, ?3, ?4)",
+        params![
+            schedule.id as i64,
+            schedule.start_utc().timestamp(),
+            schedule.last_end().map(|end| end.timestamp()),
+            serde_json::to_string(schedule)?,
+        ],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recurrence::{Frequency, Recurrence};
+    use crate::test_dir;
+    use chrono::{NaiveDate, NaiveDateTime};
+    use rstest::rstest;
+
+    fn at(day: u32, hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+    }
+
+    fn utc(day: u32) -> DateTime<Utc> {
+        at(day, 0).and_utc()
+    }
+
+    #[rstest]
+    #[case("schedule.json", BackendKind::Json)]
+    #[case("schedule.db", BackendKind::Sqlite)]
+    #[case("schedule", BackendKind::Json)]
+    fn test_backend_kind(#[case] path: &str, #[case] expected: BackendKind) {
+        assert_eq!(expected, BackendKind::of(Path::new(path)));
+    }
+
+    #[test]
+    fn test_sqlite_backend() {
+        let dir = test_dir("backend");
+        std::fs::create_dir_all(&dir).unwrap();
+        let backend = open(&dir.join("schedule.db"));
+
+        let empty = backend.load().unwrap();
+        let mut calendar = Calendar {
+            schedules: vec![
+                Schedule::test(0, "予定0", at(1, 10), at(1, 11)),
+                Schedule::test(1, "予定1", at(10, 10), at(10, 11)),
+                Schedule {
+                    recurrence: Some(Recurrence {
+                        count: Some(3),
+                        ..Recurrence::new(Frequency::Daily)
+                    }),
+                    ..Schedule::test(2, "予定2", at(2, 10), at(2, 11))
+                },
+                // 終わりの無い繰り返しはどの期間にも含まれうる
+                Schedule {
+                    recurrence: Some(Recurrence::new(Frequency::Weekly)),
+                    ..Schedule::test(3, "予定3", at(1, 10), at(1, 11))
+                },
+            ],
+            next_id: 4,
+        };
+        backend.save(&calendar, Change::All).unwrap();
+        let all = backend.load().unwrap();
+
+        let mut range = backend.load_range(utc(4), utc(11)).unwrap();
+        let ids = |calendar: &Calendar| -> Vec<u64> {
+            calendar
+                .schedules
+                .iter()
+                .map(|schedule| schedule.id)
+                .collect()
+        };
+        let in_range = ids(&range);
+
+        let id = range.insert(Schedule::test(0, "予定4", at(20, 10), at(20, 11)));
+        backend.save(&range, Change::Put(id)).unwrap();
+        backend.save(&range, Change::Delete(0)).unwrap();
+        calendar = backend.load().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // ファイルがまだ無ければ空のカレンダー
+        assert_eq!(Calendar::default(), empty);
+        assert_eq!(4, all.schedules.len());
+        assert_eq!(4, all.next_id);
+        assert_eq!(vec![1, 2, 3], in_range);
+        assert_eq!(vec![1, 2, 3, 4], ids(&calendar));
+        assert_eq!(5, calendar.next_id);
+    }
+}
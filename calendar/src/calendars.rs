@@ -0,0 +1,159 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::PathBuf,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::backend::{self, BackendKind, Change};
+use crate::{storage, Calendar, MyError};
+
+/// --calendar を省略したときのカレンダー名 (以前と同じ schedule.json を使う)
+pub const DEFAULT_CALENDAR: &str = "schedule";
+
+// 名前が . で始まるカレンダーは作れないので、設定ファイルと衝突しない
+const SUBSCRIPTIONS_FILE: &str = ".subscriptions.json";
+
+/// 名前付きカレンダーを置くデータディレクトリ
+pub struct DataDir {
+    root: PathBuf,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Subscriptions {
+    calendars: Vec<String>,
+}
+
+impl DataDir {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    /// カレンダーのファイル。SQLite に移したカレンダーはそちらを使う
+    pub fn calendar_path(&self, name: &str) -> PathBuf {
+        let sqlite = self.calendar_path_for(name, BackendKind::Sqlite);
+        if sqlite.exists() {
+            return sqlite;
+        }
+        self.calendar_path_for(name, BackendKind::Json)
+    }
+
+    pub fn calendar_path_for(&self, name: &str, kind: BackendKind) -> PathBuf {
+        self.root.join(format!("{}.{}", name, kind.extension()))
+    }
+
+    /// データディレクトリにあるカレンダーの名前を返す
+    ///
+    /// データディレクトリは既定でカレントディレクトリなので、拡張子が同じでもカレンダーとして読めないファイルは除く
+    pub fn calendar_names(&self) -> io::Result<Vec<String>> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&self.root)? {
+            let path = entry?.path();
+            let extensions = [BackendKind::Json, BackendKind::Sqlite].map(BackendKind::extension);
+            if path
+                .extension()
+                .is_some_and(|extension| extensions.iter().any(|known| extension == *known))
+            {
+                if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    if parse_name(name).is_ok() && backend::is_calendar(&path) {
+                        names.push(name.to_string());
+                    }
+                }
+            }
+        }
+        names.sort();
+        names.dedup();
+        Ok(names)
+    }
+
+    /// 購読しているカレンダーの名前。設定ファイルが無ければ空
+    pub fn subscriptions(&self) -> Result<Vec<String>, MyError> {
+        let path = self.subscriptions_path();
+        if !path.exists() {
+            return Ok(vec![]);
+        }
+        let reader = BufReader::new(File::open(&path)?);
+        let subscriptions: Subscriptions =
+            serde_json::from_reader(reader).map_err(|source| MyError::Parse { path, source })?;
+        Ok(subscriptions.calendars)
+    }
+
+    pub fn save_subscriptions(&self, calendars: Vec<String>) -> Result<(), MyError> {
+        storage::write_atomic(&self.subscriptions_path(), |file| {
+            let mut writer = BufWriter::new(file);
+            serde_json::to_writer(&mut writer, &Subscriptions { calendars })?;
+            writer.flush()
+        })?;
+        Ok(())
+    }
+
+    fn subscriptions_path(&self) -> PathBuf {
+        self.root.join(SUBSCRIPTIONS_FILE)
+    }
+
+    /// 空のカレンダーを指定の形式で作る
+    pub fn create_calendar(&self, name: &str, kind: BackendKind) -> Result<(), MyError> {
+        if self.calendar_path(name).exists() {
+            return Err(MyError::CalendarExists(name.to_string()));
+        }
+        backend::open(&self.calendar_path_for(name, kind)).save(&Calendar::default(), Change::All)
+    }
+}
+
+/// カレンダー名はファイル名になるので、パスとして解釈される文字を拒否する
+pub fn parse_name(name: &str) -> Result<String, String> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && !name.contains(['/', '\\', ':'])
+        && !name.chars().any(char::is_control);
+    if valid {
+        Ok(name.to_string())
+    } else {
+        Err(format!(
+            "カレンダー名に使えない文字が含まれています: {}",
+            name
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("work", true)]
+    #[case("チーム", true)]
+    #[case("team-2024", true)]
+    #[case("", false)]
+    #[case(".subscriptions", false)]
+    #[case("../etc/passwd", false)]
+    #[case("a\\b", false)]
+    fn test_parse_name(#[case] name: &str, #[case] valid: bool) {
+        assert_eq!(valid, parse_name(name).is_ok());
+    }
+
+    #[test]
+    fn test_calendar_names_skip_other_files() {
+        let dir = test_dir("calendars");
+        let data_dir = DataDir::new(dir.clone());
+        fs::create_dir_all(&dir).unwrap();
+        data_dir.create_calendar("work", BackendKind::Json).unwrap();
+        data_dir
+            .create_calendar("team", BackendKind::Sqlite)
+            .unwrap();
+        fs::write(dir.join("package.json"), r#"{"name":"app"}"#).unwrap();
+        fs::write(dir.join("notes.db"), "not sqlite").unwrap();
+        rusqlite::Connection::open(dir.join("other.db"))
+            .unwrap()
+            .execute_batch("CREATE TABLE items (id INTEGER)")
+            .unwrap();
+
+        let names = data_dir.calendar_names().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(vec!["team", "work"], names);
+    }
+}
@@ -0,0 +1,136 @@
+use chrono::TimeDelta;
+
+// 指定できる長さの上限 (約1万年)
+const MAX_DAYS: i64 = 366 * 10000;
+
+/// 30m / 1h30m / 2d のような長さを解釈する (単位は d, h, m, s)
+pub fn parse_duration(text: &str) -> Result<TimeDelta, String> {
+    let invalid = || format!("長さを解釈できません: {} (例: 30m, 1h30m, 2d)", text);
+
+    let mut total = TimeDelta::zero();
+    let mut number = String::new();
+    for c in text.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let n: i64 = number.parse().map_err(|_| invalid())?;
+        number.clear();
+        let delta = match c {
+            'd' => TimeDelta::try_days(n),
+            'h' => TimeDelta::try_hours(n),
+            'm' => TimeDelta::try_minutes(n),
+            's' => TimeDelta::try_seconds(n),
+            _ => return Err(invalid()),
+        };
+        total = delta
+            .and_then(|delta| total.checked_add(&delta))
+            .ok_or_else(invalid)?;
+    }
+    if !number.is_empty() || total.is_zero() {
+        return Err(invalid());
+    }
+    // 日時に足しても表せる範囲に収まるよう、長すぎる長さは受け付けない
+    if total > TimeDelta::days(MAX_DAYS) {
+        return Err(invalid());
+    }
+    Ok(total)
+}
+
+/// 先頭に - を付けると負の長さになる parse_duration
+pub fn parse_signed_duration(text: &str) -> Result<TimeDelta, String> {
+    let text = text.trim();
+    match text.strip_prefix('-') {
+        Some(rest) => parse_duration(rest).map(|duration| -duration),
+        None => parse_duration(text.strip_prefix('+').unwrap_or(text)),
+    }
+}
+
+/// parse_duration で読める形式にする (例: 1d2h30m)
+pub fn format_duration(duration: TimeDelta) -> String {
+    let mut rest = duration.num_seconds().abs();
+    let mut text = String::new();
+    if duration < TimeDelta::zero() {
+        text.push('-');
+    }
+    for (unit, seconds) in [('d', 86400), ('h', 3600), ('m', 60), ('s', 1)] {
+        if rest >= seconds {
+            text += &format!("{}{}", rest / seconds, unit);
+            rest %= seconds;
+        }
+    }
+    if text.is_empty() || text == "-" {
+        text = "0s".to_string();
+    }
+    text
+}
+
+/// 長さのリストを ["10m", "1d"] のような文字列の配列で保存する (#[serde(with)] 用)
+pub mod text_list {
+    use chrono::TimeDelta;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        durations: &[TimeDelta],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(
+            durations
+                .iter()
+                .map(|duration| super::format_duration(*duration)),
+        )
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<TimeDelta>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|text| super::parse_duration(text).map_err(D::Error::custom))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("30m", Some(TimeDelta::minutes(30)))]
+    #[case("1h30m", Some(TimeDelta::minutes(90)))]
+    #[case("2d", Some(TimeDelta::days(2)))]
+    #[case("45s", Some(TimeDelta::seconds(45)))]
+    #[case("30", None)]
+    #[case("0m", None)]
+    #[case("1w", None)]
+    #[case("", None)]
+    // TimeDelta で表せない長さや、足すと溢れる長さ
+    #[case("99999999999999999d", None)]
+    #[case("99999999999999d", None)]
+    #[case("9223372036854775807s1s", None)]
+    #[case("3660001d", None)]
+    #[case("3660000d", Some(TimeDelta::days(3660000)))]
+    fn test_parse_duration(#[case] text: &str, #[case] expected: Option<TimeDelta>) {
+        assert_eq!(expected, parse_duration(text).ok());
+    }
+
+    #[rstest]
+    #[case("-1d", Some(TimeDelta::days(-1)))]
+    #[case("+2h", Some(TimeDelta::hours(2)))]
+    #[case("15m", Some(TimeDelta::minutes(15)))]
+    #[case("--1d", None)]
+    fn test_parse_signed_duration(#[case] text: &str, #[case] expected: Option<TimeDelta>) {
+        assert_eq!(expected, parse_signed_duration(text).ok());
+    }
+
+    #[rstest]
+    #[case(TimeDelta::minutes(10), "10m")]
+    #[case(TimeDelta::minutes(90), "1h30m")]
+    #[case(TimeDelta::days(1) + TimeDelta::seconds(5), "1d5s")]
+    #[case(TimeDelta::hours(-2), "-2h")]
+    fn test_format_duration(#[case] duration: TimeDelta, #[case] expected: &str) {
+        assert_eq!(expected, format_duration(duration));
+        assert_eq!(Ok(duration), parse_signed_duration(expected));
+    }
+}
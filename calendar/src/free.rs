@@ -0,0 +1,164 @@
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveTime, TimeDelta, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+
+use crate::{expand_between, time_zone, Calendar};
+
+/// 空き時間の条件
+pub struct FreeQuery {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub work_start: NaiveTime,
+    pub work_end: NaiveTime,
+    pub min_duration: TimeDelta,
+    pub time_zone: Tz,
+    // これより前の時間帯は空いていても返さない
+    pub now: DateTime<Utc>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct FreeSlot {
+    pub start: DateTime<FixedOffset>,
+    pub end: DateTime<FixedOffset>,
+    pub minutes: i64,
+}
+
+/// from から to までの各日の勤務時間のうち、どの予定とも重ならない時間帯を返す
+pub fn find_free_slots(calendar: &Calendar, query: &FreeQuery) -> Vec<FreeSlot> {
+    let windows = work_windows(query);
+    let busy = busy_intervals(calendar, &windows);
+
+    let mut slots = Vec::new();
+    for (window_start, window_end) in windows {
+        let mut cursor = window_start.max(query.now);
+        for (busy_start, busy_end) in &busy {
+            if *busy_end <= cursor || *busy_start >= window_end {
+                continue;
+            }
+            push_slot(&mut slots, cursor, *busy_start, query);
+            cursor = cursor.max(*busy_end);
+        }
+        push_slot(&mut slots, cursor, window_end, query);
+    }
+    slots
+}
+
+/// from から to までの各日の勤務時間帯
+pub fn work_windows(query: &FreeQuery) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    query
+        .from
+        .iter_days()
+        .take_while(|date| *date <= query.to)
+        .map(|date| {
+            (
+                time_zone::to_utc(query.time_zone, date.and_time(query.work_start)),
+                time_zone::to_utc(query.time_zone, date.and_time(query.work_end)),
+            )
+        })
+        .collect()
+}
+
+/// 勤務時間帯にかかる、埋まっている時間を開始順に返す
+///
+/// 繰り返し予定も展開し、Schedule::intersects と同じく [start, end) を埋まっている時間とする。
+/// 仮の予定や空き扱いの予定は埋まっている時間に含めない
+pub fn busy_intervals(
+    calendar: &Calendar,
+    windows: &[(DateTime<Utc>, DateTime<Utc>)],
+) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let (Some(first), Some(last)) = (windows.first(), windows.last()) else {
+        return vec![];
+    };
+    let mut busy: Vec<_> = expand_between(calendar, first.0, last.1)
+        .iter()
+        .filter(|schedule| schedule.status.is_busy())
+        .map(|schedule| (schedule.start_utc(), schedule.end_utc()))
+        .collect();
+    busy.sort();
+    busy
+}
+
+fn push_slot(
+    slots: &mut Vec<FreeSlot>,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    query: &FreeQuery,
+) {
+    if end - start < query.min_duration || end <= start {
+        return;
+    }
+    slots.push(FreeSlot {
+        start: start.with_timezone(&query.time_zone).fixed_offset(),
+        end: end.with_timezone(&query.time_zone).fixed_offset(),
+        minutes: (end - start).num_minutes(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recurrence::{Frequency, Recurrence};
+    use crate::{Schedule, Status};
+    use chrono::NaiveDateTime;
+
+    fn dt(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    fn slots(calendar: &Calendar, query: &FreeQuery) -> Vec<(NaiveDateTime, NaiveDateTime)> {
+        find_free_slots(calendar, query)
+            .iter()
+            .map(|slot| (slot.start.naive_local(), slot.end.naive_local()))
+            .collect()
+    }
+
+    #[test]
+    fn test_find_free_slots() {
+        let calendar = Calendar {
+            schedules: vec![
+                Schedule::test(0, "予定0", dt(1, 10, 0), dt(1, 11, 0)),
+                // 前の予定とちょうど接している予定の間に空きは無い
+                Schedule::test(1, "予定1", dt(1, 11, 0), dt(1, 11, 45)),
+                // 空きが 20 分しかない
+                Schedule::test(2, "予定2", dt(1, 12, 5), dt(1, 17, 40)),
+                Schedule {
+                    recurrence: Some(Recurrence {
+                        count: Some(2),
+                        ..Recurrence::new(Frequency::Daily)
+                    }),
+                    ..Schedule::test(3, "予定3", dt(1, 8, 0), dt(1, 9, 30))
+                },
+                // 仮の予定は時間をふさがない
+                Schedule {
+                    status: Status::Tentative,
+                    ..Schedule::test(4, "予定4", dt(2, 13, 0), dt(2, 14, 0))
+                },
+            ],
+            next_id: 5,
+        };
+        let query = FreeQuery {
+            from: dt(1, 0, 0).date(),
+            to: dt(2, 0, 0).date(),
+            work_start: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+            work_end: NaiveTime::from_hms_opt(18, 0, 0).unwrap(),
+            min_duration: TimeDelta::minutes(30),
+            time_zone: Tz::UTC,
+            now: time_zone::to_utc(Tz::UTC, dt(1, 0, 0)),
+        };
+
+        assert_eq!(
+            vec![(dt(1, 9, 30), dt(1, 10, 0)), (dt(2, 9, 30), dt(2, 18, 0))],
+            slots(&calendar, &query)
+        );
+
+        // 現在時刻より前は返さない
+        let query = FreeQuery {
+            now: time_zone::to_utc(Tz::UTC, dt(2, 12, 0)),
+            ..query
+        };
+        assert_eq!(vec![(dt(2, 12, 0), dt(2, 18, 0))], slots(&calendar, &query));
+    }
+}
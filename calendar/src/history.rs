@@ -0,0 +1,319 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{storage, Calendar, MyError, Schedule};
+
+/// 操作の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Add,
+    Edit,
+    Delete,
+    Undo,
+    Redo,
+}
+
+impl Action {
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Add => "追加",
+            Action::Edit => "変更",
+            Action::Delete => "削除",
+            Action::Undo => "取り消し",
+            Action::Redo => "やり直し",
+        }
+    }
+}
+
+/// 操作の記録。予定の操作前と操作後の内容を持つ
+///
+/// before が None なら操作前は無かった予定、after が None なら操作後に無くなった予定
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Record {
+    pub time: DateTime<Utc>,
+    pub action: Action,
+    pub id: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub before: Option<Schedule>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<Schedule>,
+}
+
+impl Record {
+    pub fn new(
+        time: DateTime<Utc>,
+        action: Action,
+        before: Option<&Schedule>,
+        after: Option<&Schedule>,
+    ) -> Self {
+        Self {
+            time,
+            action,
+            // before と after のどちらかは必ずある
+            id: before.or(after).map_or(0, |schedule| schedule.id),
+            before: before.cloned(),
+            after: after.cloned(),
+        }
+    }
+
+    /// 予定の件名。削除なら削除前のもの
+    pub fn subject(&self) -> &str {
+        self.after
+            .as_ref()
+            .or(self.before.as_ref())
+            .map_or("", |schedule| &schedule.subject)
+    }
+}
+
+/// カレンダーの操作の記録ファイル。形式を移しても同じ記録を使う
+pub fn log_path(calendar_path: &Path) -> PathBuf {
+    calendar_path.with_extension("history.jsonl")
+}
+
+/// 1行に1件ずつ追記する。書いた記録は変更しない
+pub fn append(calendar_path: &Path, record: &Record) -> Result<(), MyError> {
+    let path = log_path(calendar_path);
+    let file_error = |source| MyError::File {
+        path: path.clone(),
+        source,
+    };
+    storage::create_parent_dir(&path).map_err(file_error)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(file_error)?;
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    file.write_all(line.as_bytes()).map_err(file_error)
+}
+
+/// 記録を古い順に読む。ファイルが無ければ空
+pub fn read(calendar_path: &Path) -> Result<Vec<Record>, MyError> {
+    let path = log_path(calendar_path);
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(source) => return Err(MyError::File { path, source }),
+    };
+    let mut records = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|source| MyError::File {
+            path: path.clone(),
+            source,
+        })?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        records.push(
+            serde_json::from_str(&line).map_err(|source| MyError::Parse {
+                path: path.clone(),
+                source,
+            })?,
+        );
+    }
+    Ok(records)
+}
+
+/// 記録をたどって、取り消せる操作とやり直せる操作の位置を求める
+///
+/// どちらも最後の要素が次に対象となる操作
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Stacks {
+    pub done: Vec<usize>,
+    pub undone: Vec<usize>,
+}
+
+pub fn stacks(records: &[Record]) -> Stacks {
+    let mut stacks = Stacks::default();
+    for (index, record) in records.iter().enumerate() {
+        match record.action {
+            Action::Add | Action::Edit | Action::Delete => {
+                stacks.done.push(index);
+                // 新しい操作をしたら、取り消した操作はやり直せなくなる
+                stacks.undone.clear();
+            }
+            Action::Undo => stacks.undone.extend(stacks.done.pop()),
+            Action::Redo => stacks.done.extend(stacks.undone.pop()),
+        }
+    }
+    stacks
+}
+
+/// 予定を from から to の状態に戻す。今の予定が from と違えば、後から変更されているので戻さない
+pub fn restore(
+    calendar: &mut Calendar,
+    id: u64,
+    from: Option<&Schedule>,
+    to: Option<&Schedule>,
+) -> Result<(), MyError> {
+    let index = calendar
+        .schedules
+        .iter()
+        .position(|schedule| schedule.id == id);
+    if index.map(|index| &calendar.schedules[index]) != from {
+        return Err(MyError::Stale(id));
+    }
+    match (index, to) {
+        (Some(index), Some(schedule)) => calendar.schedules[index] = schedule.clone(),
+        (Some(index), None) => {
+            calendar.schedules.remove(index);
+        }
+        (None, Some(schedule)) => {
+            calendar.schedules.push(schedule.clone());
+            calendar.next_id = calendar.next_id.max(id + 1);
+        }
+        (None, None) => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir;
+    use chrono::{NaiveDate, NaiveDateTime};
+    use rstest::rstest;
+
+    fn at(hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+    }
+
+    fn now() -> DateTime<Utc> {
+        NaiveDate::from_ymd_opt(2024, 1, 3)
+            .unwrap()
+            .and_hms_opt(3, 0, 0)
+            .unwrap()
+            .and_utc()
+    }
+
+    fn record(action: Action) -> Record {
+        Record::new(
+            now(),
+            action,
+            None,
+            Some(&Schedule::test(0, "a", at(10), at(11))),
+        )
+    }
+
+    #[rstest]
+    #[case(vec![], vec![], vec![])]
+    #[case(vec![Action::Add, Action::Edit], vec![0, 1], vec![])]
+    #[case(vec![Action::Add, Action::Edit, Action::Undo], vec![0], vec![1])]
+    #[case(
+        vec![Action::Add, Action::Edit, Action::Undo, Action::Undo, Action::Redo],
+        vec![0],
+        vec![1]
+    )]
+    // 取り消した後に操作すると、やり直せる操作は無くなる
+    #[case(vec![Action::Add, Action::Undo, Action::Add], vec![2], vec![])]
+    // 取り消す操作が無いときの Undo は何も変えない
+    #[case(vec![Action::Undo, Action::Redo, Action::Add], vec![2], vec![])]
+    fn test_stacks(
+        #[case] actions: Vec<Action>,
+        #[case] done: Vec<usize>,
+        #[case] undone: Vec<usize>,
+    ) {
+        let records: Vec<_> = actions.into_iter().map(record).collect();
+        assert_eq!(Stacks { done, undone }, stacks(&records));
+    }
+
+    #[test]
+    fn test_restore() {
+        let mut calendar = Calendar {
+            schedules: vec![
+                Schedule::test(0, "a", at(10), at(11)),
+                Schedule::test(1, "b", at(10), at(11)),
+            ],
+            next_id: 2,
+        };
+        let edited = Schedule::test(1, "c", at(10), at(11));
+
+        // 削除の取り消し
+        restore(
+            &mut calendar,
+            2,
+            None,
+            Some(&Schedule::test(2, "d", at(10), at(11))),
+        )
+        .unwrap();
+        // 変更の取り消し
+        restore(
+            &mut calendar,
+            1,
+            Some(&Schedule::test(1, "b", at(10), at(11))),
+            Some(&edited),
+        )
+        .unwrap();
+        // 追加の取り消し
+        restore(
+            &mut calendar,
+            0,
+            Some(&Schedule::test(0, "a", at(10), at(11))),
+            None,
+        )
+        .unwrap();
+        // 後から変更された予定は戻さない
+        let stale = restore(
+            &mut calendar,
+            1,
+            Some(&Schedule::test(1, "b", at(10), at(11))),
+            None,
+        );
+
+        assert_eq!(
+            vec![edited, Schedule::test(2, "d", at(10), at(11))],
+            calendar.schedules
+        );
+        assert_eq!(3, calendar.next_id);
+        assert!(matches!(stale, Err(MyError::Stale(1))));
+    }
+
+    #[test]
+    fn test_append_and_read() {
+        let dir = test_dir("history");
+        let path = dir.join("schedule.json");
+        let records = vec![
+            Record::new(
+                now(),
+                Action::Add,
+                None,
+                Some(&Schedule::test(0, "a", at(10), at(11))),
+            ),
+            Record::new(
+                now(),
+                Action::Edit,
+                Some(&Schedule::test(0, "a", at(10), at(11))),
+                Some(&Schedule::test(0, "b", at(10), at(11))),
+            ),
+            Record::new(
+                now(),
+                Action::Delete,
+                Some(&Schedule::test(0, "b", at(10), at(11))),
+                None,
+            ),
+        ];
+
+        let empty = read(&path).unwrap();
+        for record in &records {
+            append(&path, record).unwrap();
+        }
+        let read_back = read(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(dir.join("schedule.history.jsonl"), log_path(&path));
+        assert!(empty.is_empty());
+        assert_eq!(records, read_back);
+        assert_eq!("b", read_back[2].subject());
+    }
+}
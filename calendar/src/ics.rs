@@ -0,0 +1,925 @@
+use chrono::{NaiveDate, NaiveDateTime, TimeDelta, Utc, Weekday};
+use chrono_tz::Tz;
+
+use crate::recurrence::{Frequency, Recurrence};
+use crate::server;
+use crate::time_zone;
+use crate::{Calendar, Details, Schedule, Status};
+
+const DATE_TIME_FORMAT: &str = "%Y%m%dT%H%M%S";
+const DATE_FORMAT: &str = "%Y%m%d";
+
+// RFC 5545 では1行は75オクテットまで
+const MAX_LINE_OCTETS: usize = 75;
+
+// メールアドレスを持たない参加者を表す URI
+const NAME_URI_PREFIX: &str = "urn:x-name:";
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[error("{line}行目: {kind}")]
+pub struct ParseError {
+    pub line: usize,
+    pub kind: ParseErrorKind,
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    #[error("不正な行です: {0}")]
+    InvalidLine(String),
+    #[error("{0} がありません")]
+    MissingProperty(&'static str),
+    #[error("日時を解釈できません: {0}")]
+    InvalidDateTime(String),
+    #[error("不明なタイムゾーンです: {0}")]
+    UnknownTimeZone(String),
+    #[error("期間を解釈できません: {0}")]
+    InvalidDuration(String),
+    #[error("未対応の繰り返しルールです: {0}")]
+    UnsupportedRule(String),
+    #[error("END:VEVENT がありません")]
+    UnterminatedEvent,
+}
+
+/// 取り込んだ予定と、解釈できずに読み飛ばしたイベント
+#[derive(Debug, Default)]
+pub struct Parsed {
+    pub schedules: Vec<Schedule>,
+    pub skipped: Vec<ParseError>,
+}
+
+pub fn to_ics(calendar: &Calendar) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//rust-book//calendar//JA".to_string(),
+    ];
+    let stamp = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+
+    for schedule in &calendar.schedules {
+        lines.push("BEGIN:VEVENT".to_string());
+        lines.push(format!("UID:{}", escape_text(&uid_of(schedule))));
+        lines.push(format!("DTSTAMP:{}", stamp));
+        // 終日の予定は日付だけで書く (DTEND は最後の日の翌日)
+        let date_time = |name: &str, local: NaiveDateTime| {
+            if schedule.all_day {
+                format!("{};VALUE=DATE:{}", name, local.format(DATE_FORMAT))
+            } else {
+                format_date_time(name, schedule.time_zone, local)
+            }
+        };
+        lines.push(date_time("DTSTART", schedule.start));
+        lines.push(date_time("DTEND", schedule.end));
+        lines.push(format!("SUMMARY:{}", escape_text(&schedule.subject)));
+        lines.extend(format_details(&schedule.details));
+        match schedule.status {
+            Status::Busy => {}
+            Status::Tentative => lines.push("STATUS:TENTATIVE".to_string()),
+            Status::Free => lines.push("TRANSP:TRANSPARENT".to_string()),
+        }
+        if let Some(rule) = &schedule.recurrence {
+            lines.push(format!("RRULE:{}", format_rule(rule, schedule)));
+            for exdate in &rule.exdates {
+                lines.push(date_time("EXDATE", *exdate));
+            }
+        }
+        for before in &schedule.reminders {
+            lines.push("BEGIN:VALARM".to_string());
+            lines.push("ACTION:DISPLAY".to_string());
+            lines.push(format!("TRIGGER:{}", format_duration(-*before)));
+            lines.push(format!("DESCRIPTION:{}", escape_text(&schedule.subject)));
+            lines.push("END:VALARM".to_string());
+        }
+        lines.push("END:VEVENT".to_string());
+    }
+    lines.push("END:VCALENDAR".to_string());
+
+    lines.iter().map(|line| fold_line(line)).collect()
+}
+
+pub fn parse_ics(text: &str) -> Result<Parsed, ParseError> {
+    let mut parsed = Parsed::default();
+    let mut event: Option<(usize, Vec<ContentLine>)> = None;
+
+    for (line_number, line) in unfold_lines(text) {
+        let content = ContentLine::parse(&line).ok_or_else(|| ParseError {
+            line: line_number,
+            kind: ParseErrorKind::InvalidLine(line.clone()),
+        })?;
+
+        match (content.name.as_str(), content.value.as_str(), &mut event) {
+            ("BEGIN", "VEVENT", None) => event = Some((line_number, Vec::new())),
+            ("BEGIN", "VEVENT", Some(_)) => {
+                return Err(ParseError {
+                    line: line_number,
+                    kind: ParseErrorKind::UnterminatedEvent,
+                })
+            }
+            ("END", "VEVENT", Some(_)) => {
+                let (begin, properties) = event.take().unwrap();
+                match parse_event(&properties) {
+                    Ok(schedule) => parsed.schedules.push(schedule),
+                    Err(kind) => parsed.skipped.push(ParseError { line: begin, kind }),
+                }
+            }
+            (_, _, Some((_, properties))) => properties.push(content),
+            // VEVENT の外側 (VCALENDAR や VTIMEZONE など) は読み飛ばす
+            (_, _, None) => {}
+        }
+    }
+
+    if let Some((begin, _)) = event {
+        return Err(ParseError {
+            line: begin,
+            kind: ParseErrorKind::UnterminatedEvent,
+        });
+    }
+    Ok(parsed)
+}
+
+fn format_details(details: &Details) -> Vec<String> {
+    let mut lines = Vec::new();
+    if let Some(location) = &details.location {
+        lines.push(format!("LOCATION:{}", escape_text(location)));
+    }
+    if let Some(description) = &details.description {
+        lines.push(format!("DESCRIPTION:{}", escape_text(description)));
+    }
+    if !details.tags.is_empty() {
+        let tags: Vec<_> = details.tags.iter().map(|tag| escape_text(tag)).collect();
+        lines.push(format!("CATEGORIES:{}", tags.join(",")));
+    }
+    for attendee in &details.attendees {
+        // メールアドレスでない参加者は名前を urn:x-name: の URI にし、表示名 (CN) も添える
+        if attendee.contains('@') {
+            lines.push(format!("ATTENDEE:mailto:{}", attendee));
+        } else {
+            lines.push(format!(
+                "ATTENDEE;CN=\"{}\":{}{}",
+                attendee.replace('"', "'"),
+                NAME_URI_PREFIX,
+                percent_encode(attendee)
+            ));
+        }
+    }
+    lines
+}
+
+// URI に使えない文字を %XX にする
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::new();
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+fn parse_details(properties: &[ContentLine]) -> Details {
+    let text = |name: &str| {
+        properties
+            .iter()
+            .find(|content| content.name == name)
+            .map(|content| unescape_text(&content.value))
+    };
+    let all = |name: &'static str| {
+        properties
+            .iter()
+            .filter(move |content| content.name == name)
+    };
+    Details {
+        location: text("LOCATION"),
+        description: text("DESCRIPTION"),
+        attendees: all("ATTENDEE")
+            .filter_map(|content| {
+                let strip = |prefix: &str| {
+                    content
+                        .value
+                        .get(..prefix.len())
+                        .filter(|scheme| scheme.eq_ignore_ascii_case(prefix))
+                        .map(|_| &content.value[prefix.len()..])
+                };
+                let address = strip("mailto:").map(str::to_string);
+                let name = strip(NAME_URI_PREFIX).map(server::percent_decode);
+                address
+                    .or(name)
+                    .or_else(|| content.param("CN").map(str::to_string))
+            })
+            .filter(|attendee| !attendee.is_empty())
+            .collect(),
+        tags: all("CATEGORIES")
+            .flat_map(|content| split_text_list(&content.value))
+            .filter(|tag| !tag.is_empty())
+            .collect(),
+    }
+}
+
+// 時間をふさがない (TRANSPARENT) 予定は空き、仮の予定 (TENTATIVE) はそのまま
+fn parse_status(properties: &[ContentLine]) -> Status {
+    let value = |name: &str| {
+        properties
+            .iter()
+            .find(|content| content.name == name)
+            .map(|content| content.value.to_ascii_uppercase())
+    };
+    if value("TRANSP").as_deref() == Some("TRANSPARENT") {
+        Status::Free
+    } else if value("STATUS").as_deref() == Some("TENTATIVE") {
+        Status::Tentative
+    } else {
+        Status::Busy
+    }
+}
+
+/// 取り込み元の UID が無い予定には ID から UID を振る
+fn uid_of(schedule: &Schedule) -> String {
+    schedule
+        .uid
+        .clone()
+        .unwrap_or_else(|| format!("calendar-{}@localhost", schedule.id))
+}
+
+fn parse_event(all_properties: &[ContentLine]) -> Result<Schedule, ParseErrorKind> {
+    // VALARM の中のプロパティは予定のプロパティと分け、TRIGGER だけを使う
+    let mut properties = Vec::new();
+    let mut triggers = Vec::new();
+    let mut depth = 0;
+    for content in all_properties {
+        match content.name.as_str() {
+            "BEGIN" => depth += 1,
+            "END" => depth -= 1,
+            "TRIGGER" if depth > 0 => triggers.push(content),
+            _ if depth == 0 => properties.push(content.clone()),
+            _ => {}
+        }
+    }
+    let find = |name: &str| properties.iter().find(|content| content.name == name);
+
+    let subject = find("SUMMARY")
+        .map(|content| unescape_text(&content.value))
+        .unwrap_or_default();
+    let uid = find("UID").map(|content| unescape_text(&content.value));
+
+    let dtstart =
+        parse_date_time(find("DTSTART").ok_or(ParseErrorKind::MissingProperty("DTSTART"))?)?;
+    // タイムゾーンの無い日時 (floating) は手元のタイムゾーンとみなす
+    let zone = dtstart.time_zone.unwrap_or_else(time_zone::local_time_zone);
+    let start = dtstart.local;
+    let end = match (find("DTEND"), find("DURATION")) {
+        (Some(dtend), _) => parse_date_time(dtend)?.in_zone(zone),
+        (None, Some(duration)) => start
+            .checked_add_signed(parse_duration(&duration.value)?)
+            .ok_or_else(|| ParseErrorKind::InvalidDuration(duration.value.clone()))?,
+        // DTEND も DURATION も無い場合、日付のみなら1日、日時なら長さ0の予定
+        (None, None) if dtstart.is_date => start + TimeDelta::days(1),
+        (None, None) => start,
+    };
+
+    let recurrence = match find("RRULE") {
+        Some(rrule) => {
+            let mut rule = parse_rule(&rrule.value, zone)?;
+            for exdate in properties.iter().filter(|content| content.name == "EXDATE") {
+                for value in exdate.value.split(',') {
+                    let single = ContentLine {
+                        value: value.to_string(),
+                        ..exdate.clone()
+                    };
+                    rule.exdates.push(parse_date_time(&single)?.in_zone(zone));
+                }
+            }
+            Some(rule)
+        }
+        None => None,
+    };
+
+    // 開始前の通知だけを取り込む (終了基準や日時指定の TRIGGER は読み飛ばす)
+    let mut reminders = Vec::new();
+    for trigger in triggers {
+        if trigger.param("VALUE").is_some() || trigger.param("RELATED") == Some("END") {
+            continue;
+        }
+        let before = -parse_duration(&trigger.value)?;
+        if before > TimeDelta::zero() && !reminders.contains(&before) {
+            reminders.push(before);
+        }
+    }
+
+    Ok(Schedule {
+        id: 0,
+        subject,
+        details: parse_details(&properties),
+        start,
+        end,
+        time_zone: zone,
+        recurrence,
+        uid,
+        reminders,
+        all_day: dtstart.is_date,
+        status: parse_status(&properties),
+    })
+}
+
+/// DTSTART などの日時の値
+struct DateValue {
+    local: NaiveDateTime,
+    // UTC (末尾 Z) なら Tz::UTC、TZID があればそのタイムゾーン、どちらも無ければ None
+    time_zone: Option<Tz>,
+    is_date: bool,
+}
+
+impl DateValue {
+    /// 指定したタイムゾーンでの日時に直す
+    fn in_zone(&self, zone: Tz) -> NaiveDateTime {
+        match self.time_zone {
+            Some(own) if own != zone => {
+                time_zone::to_local(zone, time_zone::to_utc(own, self.local))
+            }
+            _ => self.local,
+        }
+    }
+}
+
+fn parse_date_time(content: &ContentLine) -> Result<DateValue, ParseErrorKind> {
+    let value = content.value.as_str();
+    let invalid = || ParseErrorKind::InvalidDateTime(value.to_string());
+
+    if content.param("VALUE") == Some("DATE") || !value.contains('T') {
+        let date = NaiveDate::parse_from_str(value, DATE_FORMAT).map_err(|_| invalid())?;
+        return Ok(DateValue {
+            local: date.and_hms_opt(0, 0, 0).unwrap(),
+            time_zone: None,
+            is_date: true,
+        });
+    }
+
+    let (value, time_zone) = match (value.strip_suffix('Z'), content.param("TZID")) {
+        (Some(utc), _) => (utc, Some(Tz::UTC)),
+        (None, Some(name)) => {
+            let zone = name
+                .parse()
+                .map_err(|_| ParseErrorKind::UnknownTimeZone(name.to_string()))?;
+            (value, Some(zone))
+        }
+        (None, None) => (value, None),
+    };
+    let local = NaiveDateTime::parse_from_str(value, DATE_TIME_FORMAT).map_err(|_| invalid())?;
+    Ok(DateValue {
+        local,
+        time_zone,
+        is_date: false,
+    })
+}
+
+/// UTC なら末尾 Z、それ以外は TZID 付きで日時のプロパティを書く
+fn format_date_time(name: &str, zone: Tz, local: NaiveDateTime) -> String {
+    if matches!(zone, Tz::UTC | Tz::Etc__UTC) {
+        format!("{}:{}Z", name, local.format(DATE_TIME_FORMAT))
+    } else {
+        format!(
+            "{};TZID={}:{}",
+            name,
+            zone.name(),
+            local.format(DATE_TIME_FORMAT)
+        )
+    }
+}
+
+/// P1W / P1DT2H / PT30M のような期間を解釈する
+fn parse_duration(value: &str) -> Result<TimeDelta, ParseErrorKind> {
+    let invalid = || ParseErrorKind::InvalidDuration(value.to_string());
+
+    let (negative, rest) = match value.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let rest = rest.strip_prefix('P').ok_or_else(invalid)?;
+
+    let mut total = TimeDelta::zero();
+    let mut number = String::new();
+    let mut in_time = false;
+    for c in rest.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'T' if number.is_empty() => in_time = true,
+            _ => {
+                let n: i64 = number.parse().map_err(|_| invalid())?;
+                number.clear();
+                let delta = match (c, in_time) {
+                    ('W', false) => TimeDelta::try_weeks(n),
+                    ('D', false) => TimeDelta::try_days(n),
+                    ('H', true) => TimeDelta::try_hours(n),
+                    ('M', true) => TimeDelta::try_minutes(n),
+                    ('S', true) => TimeDelta::try_seconds(n),
+                    _ => return Err(invalid()),
+                };
+                total = delta
+                    .and_then(|delta| total.checked_add(&delta))
+                    .ok_or_else(invalid)?;
+            }
+        }
+    }
+    if !number.is_empty() {
+        return Err(invalid());
+    }
+    Ok(if negative { -total } else { total })
+}
+
+/// TimeDelta を -PT10M や P1D のような期間にする
+fn format_duration(duration: TimeDelta) -> String {
+    let sign = if duration < TimeDelta::zero() {
+        "-"
+    } else {
+        ""
+    };
+    let total = duration.num_seconds().abs();
+    let days = total / 86400;
+    let times = [
+        (total / 3600 % 24, 'H'),
+        (total / 60 % 60, 'M'),
+        (total % 60, 'S'),
+    ];
+
+    let mut text = format!("{}P", sign);
+    if days > 0 {
+        text += &format!("{}D", days);
+    }
+    if times.iter().any(|(n, _)| *n > 0) {
+        text.push('T');
+        for (n, unit) in times.iter().filter(|(n, _)| *n > 0) {
+            text += &format!("{}{}", n, unit);
+        }
+    } else if days == 0 {
+        text += "T0S";
+    }
+    text
+}
+
+fn parse_rule(value: &str, zone: Tz) -> Result<Recurrence, ParseErrorKind> {
+    let unsupported = || ParseErrorKind::UnsupportedRule(value.to_string());
+
+    let mut frequency = None;
+    let mut rule = Recurrence::new(Frequency::Daily);
+    for part in value.split(';') {
+        let (key, val) = part.split_once('=').ok_or_else(unsupported)?;
+        match key {
+            "FREQ" => {
+                frequency = Some(match val {
+                    "DAILY" => Frequency::Daily,
+                    "WEEKLY" => Frequency::Weekly,
+                    "MONTHLY" => Frequency::Monthly,
+                    "YEARLY" => Frequency::Yearly,
+                    _ => return Err(unsupported()),
+                })
+            }
+            "INTERVAL" => rule.interval = val.parse().map_err(|_| unsupported())?,
+            "COUNT" => rule.count = Some(val.parse().map_err(|_| unsupported())?),
+            "UNTIL" => {
+                let until = ContentLine {
+                    name: "UNTIL".to_string(),
+                    params: vec![],
+                    value: val.to_string(),
+                };
+                rule.until = Some(parse_date_time(&until)?.in_zone(zone));
+            }
+            "BYDAY" => {
+                // 1MO のような序数付きの指定には対応しない
+                rule.by_weekday = val
+                    .split(',')
+                    .map(|day| weekday_from_ics(day).ok_or_else(unsupported))
+                    .collect::<Result<_, _>>()?;
+            }
+            "WKST" if val == "MO" => {}
+            _ => return Err(unsupported()),
+        }
+    }
+
+    rule.frequency = frequency.ok_or_else(unsupported)?;
+    if rule.interval == 0 {
+        return Err(unsupported());
+    }
+    Ok(rule)
+}
+
+fn format_rule(rule: &Recurrence, schedule: &Schedule) -> String {
+    let frequency = match rule.frequency {
+        Frequency::Daily => "DAILY",
+        Frequency::Weekly => "WEEKLY",
+        Frequency::Monthly => "MONTHLY",
+        Frequency::Yearly => "YEARLY",
+    };
+    let mut parts = vec![format!("FREQ={}", frequency)];
+    if rule.interval != 1 {
+        parts.push(format!("INTERVAL={}", rule.interval));
+    }
+    if !rule.by_weekday.is_empty() {
+        let days: Vec<_> = rule
+            .by_weekday
+            .iter()
+            .map(|day| weekday_to_ics(*day))
+            .collect();
+        parts.push(format!("BYDAY={}", days.join(",")));
+    }
+    if let Some(count) = rule.count {
+        parts.push(format!("COUNT={}", count));
+    }
+    if let Some(until) = rule.until {
+        // DTSTART が日付なら UNTIL も日付、タイムゾーンがある場合は UTC で書く決まり
+        if schedule.all_day {
+            parts.push(format!("UNTIL={}", until.format(DATE_FORMAT)));
+        } else {
+            let until = time_zone::to_utc(schedule.time_zone, until);
+            parts.push(format!("UNTIL={}Z", until.format(DATE_TIME_FORMAT)));
+        }
+    }
+    parts.join(";")
+}
+
+fn weekday_to_ics(day: Weekday) -> &'static str {
+    match day {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+fn weekday_from_ics(day: &str) -> Option<Weekday> {
+    match day {
+        "MO" => Some(Weekday::Mon),
+        "TU" => Some(Weekday::Tue),
+        "WE" => Some(Weekday::Wed),
+        "TH" => Some(Weekday::Thu),
+        "FR" => Some(Weekday::Fri),
+        "SA" => Some(Weekday::Sat),
+        "SU" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+/// NAME;PARAM=VALUE:VALUE 形式の1行
+#[derive(Debug, Clone)]
+struct ContentLine {
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl ContentLine {
+    fn parse(line: &str) -> Option<Self> {
+        // 引用符の中の ; や : は区切りとして扱わない
+        let mut in_quotes = false;
+        let mut fields = Vec::new();
+        let mut field_start = 0;
+        let mut value_start = None;
+        for (i, c) in line.char_indices() {
+            match c {
+                '"' => in_quotes = !in_quotes,
+                ';' if !in_quotes => {
+                    fields.push(&line[field_start..i]);
+                    field_start = i + 1;
+                }
+                ':' if !in_quotes => {
+                    fields.push(&line[field_start..i]);
+                    value_start = Some(i + 1);
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        let value = &line[value_start?..];
+        let (name, params) = fields.split_first()?;
+        if name.is_empty() {
+            return None;
+        }
+        let params = params
+            .iter()
+            .map(|param| {
+                let (key, val) = param.split_once('=')?;
+                Some((key.to_ascii_uppercase(), val.trim_matches('"').to_string()))
+            })
+            .collect::<Option<_>>()?;
+
+        Some(Self {
+            name: name.to_ascii_uppercase(),
+            params,
+            value: value.to_string(),
+        })
+    }
+
+    fn param(&self, key: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// 折り返された行を元に戻し、(開始行番号, 内容) を返す
+fn unfold_lines(text: &str) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some((_, last))) => last.push_str(rest),
+            _ if line.is_empty() => {}
+            _ => lines.push((i + 1, line.to_string())),
+        }
+    }
+    lines
+}
+
+fn fold_line(line: &str) -> String {
+    let mut folded = String::new();
+    let mut octets = 0;
+    for c in line.chars() {
+        if octets + c.len_utf8() > MAX_LINE_OCTETS {
+            folded.push_str("\r\n ");
+            // 継続行の先頭の空白も1オクテットに数える
+            octets = 1;
+        }
+        folded.push(c);
+        octets += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// エスケープされていない , で区切ったテキストの一覧
+fn split_text_list(text: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            ',' => {
+                values.push(unescape_text(&text[start..i]));
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    values.push(unescape_text(&text[start..]));
+    values
+}
+
+fn unescape_text(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => unescaped.push('\n'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Details;
+    use rstest::rstest;
+
+    fn dt(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(year, month, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let calendar = Calendar {
+            schedules: vec![
+                Schedule {
+                    details: Details {
+                        location: Some("会議室A, 2階".to_string()),
+                        description: Some("議題:\n- 予算".to_string()),
+                        attendees: vec!["alice@example.com".to_string(), "山田".to_string()],
+                        tags: vec!["work".to_string(), "a,b".to_string()],
+                    },
+                    ..Schedule::test(
+                        3,
+                        "打ち合わせ; 会議室A, 2階",
+                        dt(2024, 1, 1, 10, 0),
+                        dt(2024, 1, 1, 11, 0),
+                    )
+                },
+                Schedule {
+                    time_zone: Tz::Asia__Tokyo,
+                    recurrence: Some(Recurrence {
+                        interval: 2,
+                        by_weekday: vec![Weekday::Mon, Weekday::Thu],
+                        until: Some(dt(2024, 3, 1, 0, 0)),
+                        exdates: vec![dt(2024, 1, 15, 9, 0)],
+                        ..Recurrence::new(Frequency::Weekly)
+                    }),
+                    uid: Some("standup@example.com".to_string()),
+                    reminders: vec![TimeDelta::minutes(10), TimeDelta::days(1)],
+                    status: Status::Tentative,
+                    ..Schedule::test(
+                        4,
+                        "とても長い件名の定例ミーティング、参加者全員で進捗を共有する会",
+                        dt(2024, 1, 1, 9, 0),
+                        dt(2024, 1, 1, 9, 30),
+                    )
+                },
+                Schedule {
+                    // 日付だけの予定は手元のタイムゾーンとして取り込まれる
+                    time_zone: time_zone::local_time_zone(),
+                    recurrence: Some(Recurrence {
+                        until: Some(dt(2026, 8, 13, 0, 0)),
+                        ..Recurrence::new(Frequency::Yearly)
+                    }),
+                    all_day: true,
+                    status: Status::Free,
+                    ..Schedule::test(5, "夏休み", dt(2024, 8, 13, 0, 0), dt(2024, 8, 16, 0, 0))
+                },
+            ],
+            next_id: 6,
+        };
+
+        let ics = to_ics(&calendar);
+        assert!(ics.contains("DTSTART;VALUE=DATE:20240813\r\n"));
+        assert!(ics.contains("RRULE:FREQ=YEARLY;UNTIL=20260813\r\n"));
+        assert!(ics.contains("CATEGORIES:work,a\\,b\r\n"));
+        assert!(ics.contains("ATTENDEE:mailto:alice@example.com\r\n"));
+        assert!(ics.lines().all(|line| line.len() <= MAX_LINE_OCTETS + 1));
+
+        let parsed = parse_ics(&ics).unwrap();
+        assert!(parsed.skipped.is_empty());
+
+        let expected: Vec<_> = calendar
+            .schedules
+            .iter()
+            .map(|schedule| Schedule {
+                id: 0,
+                uid: Some(uid_of(schedule)),
+                ..schedule.clone()
+            })
+            .collect();
+        assert_eq!(expected, parsed.schedules);
+    }
+
+    #[test]
+    fn test_round_trip_name_only_attendees() {
+        let schedule = Schedule {
+            details: Details {
+                attendees: vec![
+                    "山田".to_string(),
+                    "Bob \"B\" Smith".to_string(),
+                    "50%".to_string(),
+                ],
+                ..Details::default()
+            },
+            ..Schedule::test(1, "面談", dt(2024, 1, 1, 10, 0), dt(2024, 1, 1, 11, 0))
+        };
+        let calendar = Calendar {
+            schedules: vec![schedule.clone()],
+            next_id: 2,
+        };
+
+        let ics = to_ics(&calendar);
+        assert!(ics.contains("ATTENDEE;CN=\"山田\":urn:x-name:%E5%B1%B1%E7%94%B0\r\n"));
+        assert!(ics.contains("ATTENDEE;CN=\"Bob 'B' Smith\":urn:x-name:Bob%20%22B%22%20Smith\r\n"));
+        assert!(!ics.contains("invalid:nomail"));
+
+        let parsed = parse_ics(&ics).unwrap();
+        assert_eq!(schedule.details, parsed.schedules[0].details);
+    }
+
+    #[test]
+    fn test_parse_skips_unsupported_events() {
+        let ics = "BEGIN:VCALENDAR\r\n\
+                   BEGIN:VEVENT\r\n\
+                   UID:a\r\n\
+                   DTSTART;VALUE=DATE:20240210\r\n\
+                   SUMMARY:建国記念\r\n \
+                   の日\r\n\
+                   END:VEVENT\r\n\
+                   BEGIN:VEVENT\r\n\
+                   UID:b\r\n\
+                   DTSTART:20240101T090000\r\n\
+                   DURATION:PT1H\r\n\
+                   RRULE:FREQ=MONTHLY;BYDAY=1MO\r\n\
+                   END:VEVENT\r\n\
+                   END:VCALENDAR\r\n";
+
+        let parsed = parse_ics(ics).unwrap();
+        assert_eq!(1, parsed.schedules.len());
+        assert_eq!("建国記念の日", parsed.schedules[0].subject);
+        assert_eq!(dt(2024, 2, 10, 0, 0), parsed.schedules[0].start);
+        assert_eq!(dt(2024, 2, 11, 0, 0), parsed.schedules[0].end);
+        assert_eq!(8, parsed.skipped[0].line);
+        assert_eq!(
+            ParseErrorKind::UnsupportedRule("FREQ=MONTHLY;BYDAY=1MO".to_string()),
+            parsed.skipped[0].kind
+        );
+    }
+
+    #[test]
+    fn test_parse_time_zones() {
+        let ics = "BEGIN:VEVENT\r\n\
+                   DTSTART;TZID=Europe/Berlin:20240701T100000\r\n\
+                   DTEND:20240701T090000Z\r\n\
+                   RRULE:FREQ=DAILY;UNTIL=20240703T080000Z\r\n\
+                   END:VEVENT\r\n";
+
+        let parsed = parse_ics(ics).unwrap();
+        let schedule = &parsed.schedules[0];
+        assert_eq!(Tz::Europe__Berlin, schedule.time_zone);
+        assert_eq!(dt(2024, 7, 1, 11, 0), schedule.end);
+        assert_eq!(
+            Some(dt(2024, 7, 3, 10, 0)),
+            schedule.recurrence.as_ref().unwrap().until
+        );
+    }
+
+    #[test]
+    fn test_parse_skips_overflowing_duration() {
+        let ics = "BEGIN:VEVENT\r\n\
+                   DTSTART:20240101T090000Z\r\n\
+                   DURATION:P99999999D\r\n\
+                   END:VEVENT\r\n";
+
+        let parsed = parse_ics(ics).unwrap();
+        assert!(parsed.schedules.is_empty());
+        assert_eq!(
+            vec![ParseError {
+                line: 1,
+                kind: ParseErrorKind::InvalidDuration("P99999999D".to_string())
+            }],
+            parsed.skipped
+        );
+    }
+
+    #[test]
+    fn test_parse_unterminated_event() {
+        let ics = "BEGIN:VCALENDAR\nBEGIN:VEVENT\nDTSTART:20240101T090000\nEND:VCALENDAR\n";
+        assert_eq!(
+            Err(ParseError {
+                line: 2,
+                kind: ParseErrorKind::UnterminatedEvent
+            }),
+            parse_ics(ics).map(|parsed| parsed.schedules)
+        );
+    }
+
+    #[rstest]
+    #[case("PT30M", TimeDelta::minutes(30))]
+    #[case("P1DT2H", TimeDelta::hours(26))]
+    #[case("P2W", TimeDelta::weeks(2))]
+    #[case("-PT15M", TimeDelta::minutes(-15))]
+    fn test_parse_duration(#[case] value: &str, #[case] expected: TimeDelta) {
+        assert_eq!(Ok(expected), parse_duration(value));
+    }
+
+    #[rstest]
+    #[case("P99999999999999D")]
+    #[case("P99999999999999999W")]
+    #[case("PT9223372036854775807S")]
+    #[case("P1D2H")]
+    fn test_parse_duration_invalid(#[case] value: &str) {
+        assert_eq!(
+            Err(ParseErrorKind::InvalidDuration(value.to_string())),
+            parse_duration(value)
+        );
+    }
+
+    #[rstest]
+    #[case(TimeDelta::minutes(30), "PT30M")]
+    #[case(TimeDelta::hours(26), "P1DT2H")]
+    #[case(TimeDelta::weeks(2), "P14D")]
+    #[case(TimeDelta::minutes(-15), "-PT15M")]
+    #[case(TimeDelta::zero(), "PT0S")]
+    fn test_format_duration(#[case] duration: TimeDelta, #[case] expected: &str) {
+        assert_eq!(expected, format_duration(duration));
+    }
+}
@@ -0,0 +1,415 @@
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, TimeDelta, Utc};
+use chrono_tz::Tz;
+use regex::Regex;
+use serde::Serialize;
+
+use crate::{time_zone, Details, Schedule, Status};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SortKey {
+    Start,
+    End,
+    Subject,
+    Id,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Table,
+    Json,
+    Csv,
+    Markdown,
+}
+
+/// 一覧に表示する予定。複数のカレンダーをまとめて表示するときは calendar に名前が入る
+pub struct Entry {
+    pub calendar: Option<String>,
+    pub schedule: Schedule,
+}
+
+/// 件名やタグ、参加者で絞り込む条件
+#[derive(Default)]
+pub struct Filter {
+    // 大文字と小文字を区別しない部分一致
+    pub contains: Option<String>,
+    pub pattern: Option<Regex>,
+    // 指定したものをすべて持つ予定だけ (大文字と小文字は区別しない)
+    pub tags: Vec<String>,
+    pub attendees: Vec<String>,
+}
+
+impl Filter {
+    pub fn matches(&self, schedule: &Schedule) -> bool {
+        let contains = self.contains.as_ref().is_none_or(|text| {
+            schedule
+                .subject
+                .to_lowercase()
+                .contains(&text.to_lowercase())
+        });
+        let pattern = self
+            .pattern
+            .as_ref()
+            .is_none_or(|pattern| pattern.is_match(&schedule.subject));
+        let details = &schedule.details;
+        contains
+            && pattern
+            && includes_all(&details.tags, &self.tags)
+            && includes_all(&details.attendees, &self.attendees)
+    }
+}
+
+fn includes_all(values: &[String], required: &[String]) -> bool {
+    required.iter().all(|required| {
+        values
+            .iter()
+            .any(|value| value.eq_ignore_ascii_case(required))
+    })
+}
+
+/// 今日の [0時, 翌日0時)
+pub fn today_range(today: NaiveDate, zone: Tz) -> (DateTime<Utc>, DateTime<Utc>) {
+    day_range(today, 1, zone)
+}
+
+/// 今週の [月曜0時, 翌週月曜0時)
+pub fn week_range(today: NaiveDate, zone: Tz) -> (DateTime<Utc>, DateTime<Utc>) {
+    let monday = today - TimeDelta::days(today.weekday().num_days_from_monday() as i64);
+    day_range(monday, 7, zone)
+}
+
+fn day_range(first: NaiveDate, days: i64, zone: Tz) -> (DateTime<Utc>, DateTime<Utc>) {
+    let start = first.and_hms_opt(0, 0, 0).unwrap();
+    (
+        time_zone::to_utc(zone, start),
+        time_zone::to_utc(zone, start + TimeDelta::days(days)),
+    )
+}
+
+pub fn sort_entries(entries: &mut [Entry], key: SortKey) {
+    match key {
+        SortKey::Start => entries.sort_by_key(|entry| entry.schedule.start_utc()),
+        SortKey::End => entries.sort_by_key(|entry| entry.schedule.end_utc()),
+        SortKey::Subject => entries.sort_by(|a, b| a.schedule.subject.cmp(&b.schedule.subject)),
+        SortKey::Id => entries.sort_by_key(|entry| entry.schedule.id),
+    }
+}
+
+#[derive(Serialize)]
+struct JsonRow<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    calendar: Option<&'a str>,
+    id: u64,
+    subject: &'a str,
+    // 終日の予定は日付だけ (end は最後の日)
+    start: String,
+    end: String,
+    time_zone: &'a str,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    all_day: bool,
+    #[serde(skip_serializing_if = "Status::is_busy")]
+    status: Status,
+    #[serde(flatten)]
+    details: &'a Details,
+}
+
+/// 表示する開始と終了。終日の予定はタイムゾーンを変換せず、最初の日と最後の日にする
+fn display_range(schedule: &Schedule, display_zone: Tz) -> (String, String) {
+    if schedule.all_day {
+        let last = schedule.end - TimeDelta::days(1);
+        return (
+            schedule.start.date().to_string(),
+            last.date().max(schedule.start.date()).to_string(),
+        );
+    }
+    (
+        time_zone::to_local(display_zone, schedule.start_utc()).to_string(),
+        time_zone::to_local(display_zone, schedule.end_utc()).to_string(),
+    )
+}
+
+/// 予定の一覧を指定の形式の文字列にする。日時は display_zone で表示する
+pub fn render(entries: &[Entry], format: OutputFormat, display_zone: Tz) -> String {
+    let merged = entries.iter().any(|entry| entry.calendar.is_some());
+
+    if format == OutputFormat::Json {
+        let rows: Vec<_> = entries
+            .iter()
+            .map(|entry| {
+                let schedule = &entry.schedule;
+                let (start, end) = if schedule.all_day {
+                    display_range(schedule, display_zone)
+                } else {
+                    (
+                        in_zone(schedule.start_utc(), display_zone).to_rfc3339(),
+                        in_zone(schedule.end_utc(), display_zone).to_rfc3339(),
+                    )
+                };
+                JsonRow {
+                    calendar: entry.calendar.as_deref(),
+                    id: schedule.id,
+                    subject: &schedule.subject,
+                    start,
+                    end,
+                    time_zone: schedule.time_zone.name(),
+                    all_day: schedule.all_day,
+                    status: schedule.status,
+                    details: &schedule.details,
+                }
+            })
+            .collect();
+        return serde_json::to_string_pretty(&rows).unwrap() + "\n";
+    }
+
+    // 確度や場所などの列は、どれかの予定が持っているときだけ表示する
+    let has = |field: fn(&Schedule) -> bool| entries.iter().any(|entry| field(&entry.schedule));
+    let columns = [
+        ("STATUS", has(|schedule| !schedule.status.is_busy())),
+        (
+            "LOCATION",
+            has(|schedule| schedule.details.location.is_some()),
+        ),
+        ("TAGS", has(|schedule| !schedule.details.tags.is_empty())),
+        (
+            "ATTENDEES",
+            has(|schedule| !schedule.details.attendees.is_empty()),
+        ),
+    ];
+    let mut header = vec!["ID", "START", "END", "SUBJECT"];
+    if merged {
+        header.insert(0, "CALENDAR");
+    }
+    header.extend(
+        columns
+            .iter()
+            .filter(|(_, shown)| *shown)
+            .map(|(name, _)| *name),
+    );
+    let rows: Vec<Vec<String>> = entries
+        .iter()
+        .map(|entry| {
+            let schedule = &entry.schedule;
+            let (start, end) = display_range(schedule, display_zone);
+            let mut row = vec![
+                schedule.id.to_string(),
+                start,
+                end,
+                schedule.subject.clone(),
+            ];
+            if merged {
+                row.insert(0, entry.calendar.clone().unwrap_or_default());
+            }
+            let details = &schedule.details;
+            let cells = [
+                schedule.status.name().to_string(),
+                details.location.clone().unwrap_or_default(),
+                details.tags.join(","),
+                details.attendees.join(","),
+            ];
+            for ((_, shown), cell) in columns.iter().zip(cells) {
+                if *shown {
+                    row.push(cell);
+                }
+            }
+            row
+        })
+        .collect();
+
+    match format {
+        OutputFormat::Table => {
+            let mut text = header.join("\t") + "\n";
+            for row in rows {
+                text += &(row.join("\t") + "\n");
+            }
+            text
+        }
+        OutputFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(vec![]);
+            writer.write_record(&header).unwrap();
+            for row in rows {
+                writer.write_record(&row).unwrap();
+            }
+            String::from_utf8(writer.into_inner().unwrap()).unwrap()
+        }
+        OutputFormat::Markdown => {
+            let mut text = format!("| {} |\n", header.join(" | "));
+            text += &format!("|{}\n", " --- |".repeat(header.len()));
+            for row in rows {
+                let cells: Vec<_> = row.iter().map(|cell| escape_markdown(cell)).collect();
+                text += &format!("| {} |\n", cells.join(" | "));
+            }
+            text
+        }
+        OutputFormat::Json => unreachable!(),
+    }
+}
+
+fn in_zone(time: DateTime<Utc>, zone: Tz) -> DateTime<FixedOffset> {
+    time.with_timezone(&zone).fixed_offset()
+}
+
+fn escape_markdown(cell: &str) -> String {
+    cell.replace('|', "\\|").replace('\n', " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+    use rstest::rstest;
+
+    fn dt(day: u32, hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+    }
+
+    fn entry(calendar: Option<&str>, id: u64, subject: &str, day: u32) -> Entry {
+        Entry {
+            calendar: calendar.map(str::to_string),
+            schedule: Schedule::test(id, subject, dt(day, 10), dt(day, 11)),
+        }
+    }
+
+    #[rstest]
+    #[case(
+        OutputFormat::Table,
+        "ID\tSTART\tEND\tSUBJECT\n1\t2024-01-02 19:00:00\t2024-01-02 20:00:00\t打ち合わせ, A|B\n"
+    )]
+    #[case(
+        OutputFormat::Csv,
+        "ID,START,END,SUBJECT\n1,2024-01-02 19:00:00,2024-01-02 20:00:00,\"打ち合わせ, A|B\"\n"
+    )]
+    #[case(OutputFormat::Markdown, "| ID | START | END | SUBJECT |\n| --- | --- | --- | --- |\n| 1 | 2024-01-02 19:00:00 | 2024-01-02 20:00:00 | 打ち合わせ, A\\|B |\n")]
+    fn test_render(#[case] format: OutputFormat, #[case] expected: &str) {
+        let entries = vec![entry(None, 1, "打ち合わせ, A|B", 2)];
+        assert_eq!(expected, render(&entries, format, Tz::Asia__Tokyo));
+    }
+
+    #[test]
+    fn test_render_all_day() {
+        let mut holiday = entry(None, 2, "夏休み", 13);
+        holiday.schedule.start = dt(13, 0);
+        holiday.schedule.end = dt(16, 0);
+        holiday.schedule.all_day = true;
+        let entries = vec![entry(None, 1, "定例", 2), holiday];
+
+        // 終日の予定は表示するタイムゾーンに関係なく日付で表示する
+        assert_eq!(
+            "ID\tSTART\tEND\tSUBJECT\n\
+             1\t2024-01-02 19:00:00\t2024-01-02 20:00:00\t定例\n\
+             2\t2024-01-13\t2024-01-15\t夏休み\n",
+            render(&entries, OutputFormat::Table, Tz::Asia__Tokyo)
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&render(&entries, OutputFormat::Json, Tz::Asia__Tokyo)).unwrap();
+        assert_eq!(serde_json::Value::Null, json[0]["all_day"]);
+        assert_eq!(true, json[1]["all_day"]);
+        assert_eq!("2024-01-15", json[1]["end"]);
+    }
+
+    #[test]
+    fn test_render_merged_json() {
+        let entries = vec![entry(Some("work"), 1, "定例", 2)];
+        let json: serde_json::Value =
+            serde_json::from_str(&render(&entries, OutputFormat::Json, Tz::Asia__Tokyo)).unwrap();
+        assert_eq!("work", json[0]["calendar"]);
+        assert_eq!("2024-01-02T19:00:00+09:00", json[0]["start"]);
+        assert_eq!("UTC", json[0]["time_zone"]);
+    }
+
+    #[rstest]
+    #[case(Some("定例"), None, vec![1, 3])]
+    #[case(Some("REVIEW"), None, vec![2])]
+    #[case(None, Some("^定例$"), vec![1])]
+    #[case(Some("定例"), Some("振り返り"), vec![3])]
+    fn test_subject_filter(
+        #[case] contains: Option<&str>,
+        #[case] pattern: Option<&str>,
+        #[case] expected: Vec<u64>,
+    ) {
+        let filter = Filter {
+            contains: contains.map(str::to_string),
+            pattern: pattern.map(|pattern| Regex::new(pattern).unwrap()),
+            ..Default::default()
+        };
+        let entries = [
+            entry(None, 1, "定例", 1),
+            entry(None, 2, "Code Review", 2),
+            entry(None, 3, "定例の振り返り", 3),
+        ];
+        let ids: Vec<_> = entries
+            .iter()
+            .filter(|entry| filter.matches(&entry.schedule))
+            .map(|entry| entry.schedule.id)
+            .collect();
+        assert_eq!(expected, ids);
+    }
+
+    #[test]
+    fn test_week_range() {
+        // 2024年1月3日は水曜日
+        let (from, to) = week_range(dt(3, 0).date(), Tz::Asia__Tokyo);
+        assert_eq!(dt(1, 0) - TimeDelta::hours(9), from.naive_utc());
+        assert_eq!(dt(7, 15), to.naive_utc());
+    }
+
+    #[rstest]
+    #[case(&[], &[], vec![1, 2, 3])]
+    #[case(&["work"], &[], vec![1, 2])]
+    #[case(&["WORK", "urgent"], &[], vec![2])]
+    #[case(&[], &["alice"], vec![1, 3])]
+    #[case(&["work"], &["Bob"], vec![2])]
+    #[case(&["private"], &[], vec![])]
+    fn test_details_filter(
+        #[case] tags: &[&str],
+        #[case] attendees: &[&str],
+        #[case] expected: Vec<u64>,
+    ) {
+        let filter = Filter {
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            attendees: attendees.iter().map(|name| name.to_string()).collect(),
+            ..Default::default()
+        };
+        let with = |id, day, tags: &[&str], attendees: &[&str]| {
+            let mut entry = entry(None, id, "予定", day);
+            entry.schedule.details.tags = tags.iter().map(|tag| tag.to_string()).collect();
+            entry.schedule.details.attendees =
+                attendees.iter().map(|name| name.to_string()).collect();
+            entry
+        };
+        let entries = [
+            with(1, 1, &["work"], &["Alice"]),
+            with(2, 2, &["work", "urgent"], &["bob"]),
+            with(3, 3, &[], &["alice", "bob"]),
+        ];
+        let ids: Vec<_> = entries
+            .iter()
+            .filter(|entry| filter.matches(&entry.schedule))
+            .map(|entry| entry.schedule.id)
+            .collect();
+        assert_eq!(expected, ids);
+    }
+
+    #[test]
+    fn test_render_details() {
+        let mut meeting = entry(None, 1, "定例", 2);
+        meeting.schedule.details.location = Some("会議室A".to_string());
+        meeting.schedule.details.tags = vec!["work".to_string(), "weekly".to_string()];
+        let entries = vec![meeting, entry(None, 2, "昼食", 3)];
+
+        // 誰も参加者を持っていないので ATTENDEES 列は出さない
+        assert_eq!(
+            "ID\tSTART\tEND\tSUBJECT\tLOCATION\tTAGS\n\
+             1\t2024-01-02 19:00:00\t2024-01-02 20:00:00\t定例\t会議室A\twork,weekly\n\
+             2\t2024-01-03 19:00:00\t2024-01-03 20:00:00\t昼食\t\t\n",
+            render(&entries, OutputFormat::Table, Tz::Asia__Tokyo)
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&render(&entries, OutputFormat::Json, Tz::Asia__Tokyo)).unwrap();
+        assert_eq!("会議室A", json[0]["location"]);
+        assert_eq!("weekly", json[0]["tags"][1]);
+        assert_eq!(serde_json::Value::Null, json[1]["tags"]);
+    }
+}
@@ -1,50 +1,724 @@
 use std::{
-    fs::File,
-    io::{BufReader, BufWriter},
+    collections::HashSet,
+    fs::{self, File},
+    io::{BufReader, BufWriter, ErrorKind, IsTerminal, Write},
+    path::{Path, PathBuf},
 };
 
-use chrono::NaiveDateTime;
-use clap::{Parser, Subcommand};
+use backend::{BackendKind, Change};
+use calendars::{DataDir, DEFAULT_CALENDAR};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, Utc, Weekday};
+use chrono_tz::Tz;
+use clap::{Args, Parser, Subcommand};
+use list::{OutputFormat, SortKey};
+use recurrence::{Frequency, Recurrence};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use storage::FileLock;
+use when::{End, When};
+
+mod backend;
+mod calendars;
+mod duration;
+mod free;
+mod history;
+mod ics;
+mod list;
+mod recurrence;
+mod server;
+mod stats;
+mod storage;
+mod time_zone;
+mod view;
+mod watch;
+mod when;
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 struct Schedule {
     id: u64,
     subject: String,
+    #[serde(flatten)]
+    details: Details,
+    // start と end は time_zone での日時
     start: NaiveDateTime,
     end: NaiveDateTime,
+    // タイムゾーンの無い以前のファイルは、手元のタイムゾーンの予定とみなす
+    #[serde(default = "time_zone::local_time_zone")]
+    time_zone: Tz,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    recurrence: Option<Recurrence>,
+    // iCalendar から取り込んだ予定の UID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    uid: Option<String>,
+    // 開始の何分前などに通知するか
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        with = "duration::text_list"
+    )]
+    reminders: Vec<TimeDelta>,
+    // 終日の予定は start が最初の日の0時、end が最後の日の翌日の0時
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    all_day: bool,
+    #[serde(default, skip_serializing_if = "Status::is_busy")]
+    status: Status,
+}
+
+/// 予定の確度。予定が入っている (busy) ものだけが他の予定をふさぐ
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    #[default]
+    Busy,
+    /// 仮の予定
+    Tentative,
+    /// 空き時間として扱う予定
+    Free,
+}
+
+impl Status {
+    fn is_busy(&self) -> bool {
+        *self == Status::Busy
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Status::Busy => "busy",
+            Status::Tentative => "tentative",
+            Status::Free => "free",
+        }
+    }
+}
+
+/// 予定の場所や参加者など、無くてもよい情報
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct Details {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    location: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    attendees: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+}
+
+impl Details {
+    /// 共通の参加者がいるか (大文字と小文字は区別しない)。どちらも参加者がいなければ自分だけの予定とみなす
+    fn shares_attendee(&self, other: &Details) -> bool {
+        if self.attendees.is_empty() && other.attendees.is_empty() {
+            return true;
+        }
+        self.attendees.iter().any(|attendee| {
+            other
+                .attendees
+                .iter()
+                .any(|other| other.eq_ignore_ascii_case(attendee))
+        })
+    }
+}
+
+/// 予定の重なりの調べ方
+#[derive(Debug, Clone, Copy, Default)]
+struct OverlapPolicy {
+    // 参加者が共通する予定同士だけを重なりとみなす
+    per_attendee: bool,
+    // 重なっていても追加・変更する
+    allow_overlap: bool,
+}
+
+impl OverlapPolicy {
+    fn conflicts(&self, a: &Schedule, b: &Schedule) -> bool {
+        self.applies(a, b) && a.intersects(b)
+    }
+
+    /// 重なるなら、最初に重なる期間を返す
+    fn overlap(&self, a: &Schedule, b: &Schedule) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        if !self.applies(a, b) {
+            return None;
+        }
+        a.first_overlap(b)
+    }
+
+    // 両方とも予定が入っていて、重なりを調べる対象になる組か
+    fn applies(&self, a: &Schedule, b: &Schedule) -> bool {
+        if !a.status.is_busy() || !b.status.is_busy() {
+            return false;
+        }
+        !self.per_attendee || a.details.shares_attendee(&b.details)
+    }
 }
 
 impl Schedule {
+    /// 終了が開始より後になっているか
+    fn check_range(&self) -> Result<(), MyError> {
+        if self.end <= self.start {
+            return Err(MyError::InvalidRange {
+                start: self.start.to_string(),
+                end: self.end.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    fn start_utc(&self) -> DateTime<Utc> {
+        time_zone::to_utc(self.time_zone, self.start)
+    }
+
+    fn end_utc(&self) -> DateTime<Utc> {
+        time_zone::to_utc(self.time_zone, self.end)
+    }
+
     fn intersects(&self, other: &Schedule) -> bool {
-        self.start < other.end && other.start < self.end
+        self.first_overlap(other).is_some()
+    }
+
+    /// 繰り返しも展開して、最初に重なる発生同士の重なっている期間を返す
+    fn first_overlap(&self, other: &Schedule) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        // 重なりうるのは両方の予定が始まった後から、どちらかが終わるまで
+        let from = self.start_utc().max(other.start_utc());
+        let to = match (self.last_end(), other.last_end()) {
+            (Some(a), Some(b)) => a.min(b),
+            (Some(end), None) | (None, Some(end)) => end,
+            (None, None) => from
+                .checked_add_signed(TimeDelta::days(RECURRENCE_HORIZON_DAYS))
+                .unwrap_or(DateTime::<Utc>::MAX_UTC),
+        };
+
+        let mine = self.occurrences(from, to);
+        let theirs = other.occurrences(from, to);
+
+        // どちらも開始順に並んでいるので、先に終わる方を進めながら比較する
+        let (mut i, mut j) = (0, 0);
+        while i < mine.len() && j < theirs.len() {
+            let (a, b) = (&mine[i], &theirs[j]);
+            if a.start_utc() < b.end_utc() && b.start_utc() < a.end_utc() {
+                return Some((
+                    a.start_utc().max(b.start_utc()),
+                    a.end_utc().min(b.end_utc()),
+                ));
+            }
+            if a.end_utc() <= b.end_utc() {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        None
+    }
+
+    /// 期間 [from, to) に重なる発生を、繰り返しを展開した予定として返す
+    fn occurrences(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<Schedule> {
+        let Some(rule) = &self.recurrence else {
+            if self.start_utc() < to && from < self.end_utc() {
+                return vec![self.clone()];
+            }
+            return vec![];
+        };
+
+        // 繰り返しは壁時計の日時で展開するので、夏時間をまたいでも同じ時刻になる
+        let duration = self.end - self.start;
+        let template = Schedule {
+            recurrence: None,
+            ..self.clone()
+        };
+        rule.starts(self.start)
+            .map(|start| Schedule {
+                start,
+                end: start + duration,
+                ..template.clone()
+            })
+            .take_while(|occurrence| occurrence.start_utc() < to)
+            .filter(|occurrence| from < occurrence.end_utc())
+            .collect()
+    }
+
+    /// 最後の発生の終了時刻。終わりなく繰り返す場合は None
+    ///
+    /// 回数や終了日時があっても RECURRENCE_HORIZON_DAYS より先まで続く繰り返しは、
+    /// 最後まで数えずに終わりのない繰り返しとみなす
+    fn last_end(&self) -> Option<DateTime<Utc>> {
+        match &self.recurrence {
+            None => Some(self.end_utc()),
+            Some(rule) if rule.is_unbounded() => None,
+            Some(rule) => {
+                let horizon = self
+                    .start
+                    .checked_add_signed(TimeDelta::days(RECURRENCE_HORIZON_DAYS))?;
+                let duration = self.end - self.start;
+                let mut last = None;
+                for start in rule.starts(self.start) {
+                    if start > horizon {
+                        return None;
+                    }
+                    last = Some(start);
+                }
+                let end = last.map_or(self.end, |start| start + duration);
+                Some(time_zone::to_utc(self.time_zone, end))
+            }
+        }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg(test)]
+impl Schedule {
+    /// テスト用の UTC の予定。ほかの項目は `..Schedule::test(..)` で上書きする
+    fn test(id: u64, subject: &str, start: NaiveDateTime, end: NaiveDateTime) -> Self {
+        Self {
+            id,
+            subject: subject.to_string(),
+            details: Details::default(),
+            start,
+            end,
+            time_zone: Tz::UTC,
+            recurrence: None,
+            uid: None,
+            reminders: vec![],
+            all_day: false,
+            status: Status::Busy,
+        }
+    }
+}
+
+/// テスト用の一時ディレクトリ。テストごとに name を変えて、並行に走っても重ならないようにする
+#[cfg(test)]
+fn test_dir(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("calendar-test-{}-{}", std::process::id(), name))
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 struct Calendar {
     schedules: Vec<Schedule>,
+    // 次に割り当てる ID。削除した予定の ID は再利用しない
+    #[serde(default)]
+    next_id: u64,
+}
+
+impl Calendar {
+    fn get(&self, id: u64) -> Option<&Schedule> {
+        self.schedules.iter().find(|schedule| schedule.id == id)
+    }
+
+    /// 新しい ID を割り当てて予定を追加し、その ID を返す
+    fn insert(&mut self, schedule: Schedule) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.schedules.push(Schedule { id, ..schedule });
+        id
+    }
+
+    /// 重複した ID を振り直し、(元の ID, 新しい ID) の組を返す
+    ///
+    /// 以前は予定の件数から ID を決めていたため、削除後に追加すると ID が重複していた
+    fn repair_ids(&mut self) -> Vec<(u64, u64)> {
+        let max_id = self.schedules.iter().map(|schedule| schedule.id).max();
+        self.next_id = self.next_id.max(max_id.map_or(0, |id| id + 1));
+
+        let mut seen = HashSet::new();
+        let mut reassigned = Vec::new();
+        for schedule in &mut self.schedules {
+            if !seen.insert(schedule.id) {
+                reassigned.push((schedule.id, self.next_id));
+                schedule.id = self.next_id;
+                self.next_id += 1;
+            }
+        }
+        reassigned
+    }
 }
 
-const SCHEDULE_FILE: &str = "schedule.json";
+// 終わりのない繰り返し同士の重複チェックで展開する日数
+const RECURRENCE_HORIZON_DAYS: i64 = 366 * 4;
+
+// 期間指定なしの List で、終わりのない繰り返しを展開する日数
+const DEFAULT_EXPAND_DAYS: i64 = 30;
+
+// 終わりの日を指定しない Free で探す日数 (最初の日を含めて1週間)
+const FREE_SEARCH_DAYS: i64 = 6;
 
 #[derive(Parser)]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// カレンダーを置くディレクトリ
+    #[arg(long, global = true, env = "CALENDAR_DIR", default_value = ".")]
+    data_dir: PathBuf,
+
+    /// 操作するカレンダーの名前
+    #[arg(long, global = true, default_value = DEFAULT_CALENDAR, value_parser = calendars::parse_name)]
+    calendar: String,
+
+    /// 今の時刻 (RFC 3339)。テストで相対的な日時を固定するために使う
+    #[arg(long, global = true, env = "CALENDAR_NOW", hide = true)]
+    now: Option<DateTime<Utc>>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    List,
+    List {
+        /// 表示期間の開始
+        #[arg(long)]
+        from: Option<NaiveDateTime>,
+        /// 表示期間の終了
+        #[arg(long)]
+        to: Option<NaiveDateTime>,
+        /// 今日の予定だけを表示する
+        #[arg(long, conflicts_with_all = ["from", "to", "week"])]
+        today: bool,
+        /// 今週 (月曜から日曜) の予定だけを表示する
+        #[arg(long, conflicts_with_all = ["from", "to"])]
+        week: bool,
+        /// 件名に含まれる文字列で絞り込む (大文字と小文字は区別しない)
+        #[arg(long)]
+        subject: Option<String>,
+        /// 件名を正規表現で絞り込む
+        #[arg(long)]
+        regex: Option<Regex>,
+        /// 並べ替えの基準 (省略時は登録順)
+        #[arg(long)]
+        sort: Option<SortKey>,
+        /// 出力形式
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+        /// 表示するタイムゾーン (例: Asia/Tokyo)。省略時は手元のタイムゾーン
+        #[arg(long)]
+        tz: Option<Tz>,
+        /// タグで絞り込む (複数指定するとすべてを持つ予定)
+        #[arg(long, value_delimiter = ',')]
+        tag: Vec<String>,
+        /// 参加者で絞り込む (複数指定するとすべてが参加する予定)
+        #[arg(long, value_delimiter = ',')]
+        attendee: Vec<String>,
+        /// 購読しているカレンダーもまとめて表示する
+        #[arg(long)]
+        merge: bool,
+    },
     Add {
         subject: String,
-        start: NaiveDateTime,
-        end: NaiveDateTime,
+        /// 開始日時 (例: 2024-01-01T10:00, tomorrow 15:00, next mon 9:30, +2h)。日付だけなら終日の予定になる
+        start: String,
+        /// 終了日時 (+1h30m なら開始からの長さ)。終日の予定では最後の日 (省略すると1日だけの予定)
+        #[arg(allow_hyphen_values = true)]
+        end: Option<String>,
+        /// 終了の代わりに予定の長さを指定する (例: 45m, 1h30m)
+        #[arg(long, value_parser = duration::parse_duration, conflicts_with = "end")]
+        duration: Option<TimeDelta>,
+        /// 予定のタイムゾーン (例: Europe/Berlin)。省略時は手元のタイムゾーン
+        #[arg(long)]
+        tz: Option<Tz>,
+        #[command(flatten)]
+        recurrence: RecurrenceArgs,
+        #[command(flatten)]
+        details: DetailsArgs,
+        /// 予定の確度。busy の予定だけが他の予定と重ならないように調べられる
+        #[arg(long, value_enum, default_value_t)]
+        status: Status,
+        /// 開始前に通知する (例: 10m, 1d。複数指定可)
+        #[arg(long = "remind", value_parser = duration::parse_duration)]
+        reminders: Vec<TimeDelta>,
+        /// 購読しているカレンダーの予定とも重複を調べる
+        #[arg(long)]
+        check_subscribed: bool,
+        /// 参加者が共通する予定とだけ重複を調べる
+        #[arg(long)]
+        per_attendee: bool,
+        /// 既存の予定と重なっていても受け付ける
+        #[arg(long)]
+        allow_overlap: bool,
     },
     Delete {
         id: u64,
     },
+    /// 予定を変更する
+    Edit {
+        id: u64,
+        /// 新しい件名
+        #[arg(long)]
+        subject: Option<String>,
+        /// 新しい開始日時 (--end を省略すると長さを保ったまま移動する)。日付だけなら終日の予定になる
+        #[arg(long)]
+        start: Option<String>,
+        /// 新しい終了日時。終日の予定では最後の日
+        #[arg(long)]
+        end: Option<String>,
+        /// 予定をずらす長さ (例: 30m, -1d)
+        #[arg(long, value_parser = duration::parse_signed_duration, allow_hyphen_values = true, conflicts_with_all = ["start", "end"])]
+        shift: Option<TimeDelta>,
+        /// 新しいタイムゾーン (日時はそのままで、どのタイムゾーンの日時かを変える)
+        #[arg(long)]
+        tz: Option<Tz>,
+        /// 通知を指定したものに置き換える (例: 10m, 1d。複数指定可)
+        #[arg(long = "remind", value_parser = duration::parse_duration)]
+        reminders: Vec<TimeDelta>,
+        /// 通知をすべて消す
+        #[arg(long, conflicts_with = "reminders")]
+        no_remind: bool,
+        /// 新しい場所 (空文字列で消す)
+        #[arg(long)]
+        location: Option<String>,
+        /// 新しい説明 (空文字列で消す)
+        #[arg(long)]
+        description: Option<String>,
+        /// 参加者を指定したものに置き換える (例: alice,bob)
+        #[arg(long = "attendee", value_delimiter = ',')]
+        attendees: Vec<String>,
+        /// 参加者をすべて消す
+        #[arg(long, conflicts_with = "attendees")]
+        no_attendee: bool,
+        /// タグを指定したものに置き換える (例: work,urgent)
+        #[arg(long = "tag", value_delimiter = ',')]
+        tags: Vec<String>,
+        /// タグをすべて消す
+        #[arg(long, conflicts_with = "tags")]
+        no_tag: bool,
+        /// 新しい予定の確度
+        #[arg(long, value_enum)]
+        status: Option<Status>,
+        /// 購読しているカレンダーの予定とも重複を調べる
+        #[arg(long)]
+        check_subscribed: bool,
+        /// 参加者が共通する予定とだけ重複を調べる
+        #[arg(long)]
+        per_attendee: bool,
+        /// 既存の予定と重なっていても受け付ける
+        #[arg(long)]
+        allow_overlap: bool,
+    },
+    /// 最後の追加・変更・削除を取り消す
+    Undo,
+    /// 最後に取り消した操作をやり直す
+    Redo,
+    /// 予定の追加・変更・削除の記録を古い順に表示する
+    History {
+        /// 表示する件数 (新しいものから数える)
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+        /// 表示するタイムゾーン。省略時は手元のタイムゾーン
+        #[arg(long)]
+        tz: Option<Tz>,
+    },
+    /// iCalendar (.ics) ファイルから予定を取り込む
+    Import {
+        path: PathBuf,
+        /// 既存の予定と重なる予定も取り込む
+        #[arg(long)]
+        allow_overlap: bool,
+    },
+    /// iCalendar (.ics) 形式で予定を書き出す
+    Export {
+        /// 出力先 (省略時は標準出力)
+        path: Option<PathBuf>,
+    },
+    /// 予定の入っていない時間帯を探す
+    Free {
+        /// 探す期間の最初の日 (省略時は今日)
+        #[arg(long)]
+        from: Option<NaiveDate>,
+        /// 探す期間の最後の日 (省略時は最初の日から1週間)
+        #[arg(long)]
+        to: Option<NaiveDate>,
+        /// 勤務時間の開始
+        #[arg(long, default_value = "09:00")]
+        work_start: NaiveTime,
+        /// 勤務時間の終了
+        #[arg(long, default_value = "18:00")]
+        work_end: NaiveTime,
+        /// 必要な長さ (例: 30m, 1h30m)
+        #[arg(long = "min", default_value = "30m", value_parser = duration::parse_duration)]
+        min_duration: TimeDelta,
+        /// 勤務時間のタイムゾーン。省略時は手元のタイムゾーン
+        #[arg(long)]
+        tz: Option<Tz>,
+        /// JSON で出力する
+        #[arg(long)]
+        json: bool,
+        /// 購読しているカレンダーの予定も埋まっている時間とみなす
+        #[arg(long)]
+        merge: bool,
+    },
+    /// カレンダーの中で重なっている予定の組を一覧表示する
+    Conflicts {
+        /// 参加者が共通する予定同士だけを調べる
+        #[arg(long)]
+        per_attendee: bool,
+        /// 表示するタイムゾーン。省略時は手元のタイムゾーン
+        #[arg(long)]
+        tz: Option<Tz>,
+    },
+    /// 期間内の予定の時間を日・週・件名・タグごとに集計する
+    Stats {
+        /// 集計する期間の最初の日 (省略時は今週の月曜日)
+        #[arg(long)]
+        from: Option<NaiveDate>,
+        /// 集計する期間の最後の日 (省略時は最初の日から1週間)
+        #[arg(long)]
+        to: Option<NaiveDate>,
+        /// まとめる単位
+        #[arg(long, value_enum, default_value_t)]
+        by: stats::GroupBy,
+        /// --by subject で件名に含まれるかを調べるキーワード (例: 会議,review)
+        #[arg(long = "keyword", value_delimiter = ',')]
+        keywords: Vec<String>,
+        /// 勤務時間の開始 (空き時間の集計に使う)
+        #[arg(long, default_value = "09:00")]
+        work_start: NaiveTime,
+        /// 勤務時間の終了
+        #[arg(long, default_value = "18:00")]
+        work_end: NaiveTime,
+        /// 忙しい日を何日分表示するか
+        #[arg(long, default_value_t = 3)]
+        top: usize,
+        /// 出力形式
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+        /// 集計するタイムゾーン。省略時は手元のタイムゾーン
+        #[arg(long)]
+        tz: Option<Tz>,
+        /// 購読しているカレンダーの予定もまとめて集計する
+        #[arg(long)]
+        merge: bool,
+    },
+    /// 新しいカレンダーを作る
+    New {
+        #[arg(value_parser = calendars::parse_name)]
+        name: String,
+        /// 保存する形式
+        #[arg(long, value_enum, default_value_t)]
+        backend: BackendKind,
+    },
+    /// カレンダーを別の保存形式に移す
+    Migrate {
+        /// 移し先の形式
+        #[arg(long, value_enum)]
+        to: BackendKind,
+    },
+    /// カレンダーの一覧を表示する
+    Calendars,
+    /// カレンダーを購読する
+    Subscribe {
+        #[arg(value_parser = calendars::parse_name)]
+        name: String,
+    },
+    /// カレンダーの購読をやめる
+    Unsubscribe {
+        #[arg(value_parser = calendars::parse_name)]
+        name: String,
+    },
+    /// 月・週・日の表で予定を表示する
+    View {
+        /// 表示する期間
+        #[arg(value_enum, default_value_t)]
+        span: view::Span,
+        /// この日を含む期間を表示する (例: 2024-01-15, tomorrow, next mon)。省略時は今日
+        #[arg(long)]
+        date: Option<String>,
+        /// 表示するタイムゾーン。省略時は手元のタイムゾーン
+        #[arg(long)]
+        tz: Option<Tz>,
+        /// 購読しているカレンダーの予定もまとめて表示する
+        #[arg(long)]
+        merge: bool,
+    },
+    /// 常駐して、予定の通知の時刻になったら知らせる
+    Watch {
+        /// 通知の送り先
+        #[arg(long, value_enum, default_value_t)]
+        sink: watch::SinkKind,
+        /// --sink notify で実行するコマンド (件名と本文を引数に渡す)
+        #[arg(long, default_value = "notify-send")]
+        notify_command: String,
+        /// --sink webhook で POST する URL (http://localhost のような手元の URL のみ)
+        #[arg(long, value_parser = watch::parse_webhook_url, required_if_eq("sink", "webhook"))]
+        webhook: Option<watch::WebhookUrl>,
+        /// ファイルの変更と通知の時刻を調べる間隔
+        #[arg(long, default_value = "30s", value_parser = duration::parse_duration)]
+        interval: TimeDelta,
+        /// 購読しているカレンダーの予定も通知する
+        #[arg(long)]
+        merge: bool,
+    },
+    /// 予定の一覧・追加・変更・削除を JSON の API として localhost で提供する
+    Serve {
+        /// 待ち受けるポート。0 なら空いているポートを使う
+        #[arg(long, default_value_t = 8080)]
+        port: u16,
+        /// 日時の解釈と表示に使うタイムゾーン。省略時は手元のタイムゾーン
+        #[arg(long)]
+        tz: Option<Tz>,
+    },
+}
+
+#[derive(Args)]
+struct RecurrenceArgs {
+    /// 繰り返しの単位
+    #[arg(long)]
+    repeat: Option<Frequency>,
+    /// 繰り返しの間隔
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..), requires = "repeat")]
+    interval: u32,
+    /// 繰り返す曜日 (例: mon,wed,fri)
+    #[arg(long, value_delimiter = ',', requires = "repeat")]
+    by_weekday: Vec<Weekday>,
+    /// 繰り返す回数
+    #[arg(long, requires = "repeat")]
+    count: Option<u32>,
+    /// 繰り返しの終了日時
+    #[arg(long, requires = "repeat")]
+    until: Option<NaiveDateTime>,
+    /// 除外する日時 (複数指定可)
+    #[arg(long = "except", requires = "repeat")]
+    exdates: Vec<NaiveDateTime>,
+}
+
+#[derive(Args)]
+struct DetailsArgs {
+    /// 場所
+    #[arg(long)]
+    location: Option<String>,
+    /// 説明
+    #[arg(long)]
+    description: Option<String>,
+    /// 参加者 (例: alice,bob。複数指定可)
+    #[arg(long = "attendee", value_delimiter = ',')]
+    attendees: Vec<String>,
+    /// タグ (例: work,urgent。複数指定可)
+    #[arg(long = "tag", value_delimiter = ',')]
+    tags: Vec<String>,
+}
+
+impl DetailsArgs {
+    fn to_details(&self) -> Details {
+        Details {
+            location: self.location.clone(),
+            description: self.description.clone(),
+            attendees: trim_all(&self.attendees),
+            tags: trim_all(&self.tags),
+        }
+    }
+}
+
+// 前後の空白を除き、空の要素を捨てる
+fn trim_all(values: &[String]) -> Vec<String> {
+    values
+        .iter()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+impl RecurrenceArgs {
+    fn to_recurrence(&self) -> Option<Recurrence> {
+        let frequency = self.repeat?;
+        Some(Recurrence {
+            interval: self.interval,
+            by_weekday: self.by_weekday.clone(),
+            count: self.count,
+            until: self.until,
+            exdates: self.exdates.clone(),
+            ..Recurrence::new(frequency)
+        })
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -52,106 +726,1210 @@ enum MyError {
     #[error("io error: {0}")]
     Io(#[from] std::io::Error),
 
+    #[error("{}: {source}", .path.display())]
+    File {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
     #[error("json error: {0}")]
     Json(#[from] serde_json::Error),
+
+    #[error("データベースを読み書きできません: {0}")]
+    Database(#[from] rusqlite::Error),
+
+    #[error("{} を読み込めません: {source}", .path.display())]
+    Parse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[error("ics error: {0}")]
+    Ics(#[from] ics::ParseError),
+
+    #[error("ID {0} の予定がありません")]
+    NotFound(u64),
+
+    #[error("カレンダー {0} がありません")]
+    CalendarNotFound(String),
+
+    #[error("カレンダー {0} はすでにあります")]
+    CalendarExists(String),
+
+    #[error("予定が重複しています (ID: {})。重ねる場合は --allow-overlap を指定してください", join_ids(.0))]
+    Conflict(Vec<u64>),
+
+    #[error("ID {0} の予定はその後に変更されているため、元に戻せません")]
+    Stale(u64),
+
+    #[error("終了 ({end}) は開始 ({start}) より後にしてください")]
+    InvalidRange { start: String, end: String },
+
+    #[error("{0}")]
+    Usage(String),
+}
+
+impl MyError {
+    /// 終了コード (2 は clap の引数エラー)
+    fn exit_code(&self) -> i32 {
+        match self {
+            MyError::Usage(_) => 2,
+            MyError::NotFound(_) | MyError::CalendarNotFound(_) => 3,
+            MyError::Conflict(_) | MyError::CalendarExists(_) | MyError::Stale(_) => 4,
+            MyError::InvalidRange { .. } => 5,
+            MyError::Json(_) | MyError::Parse { .. } | MyError::Ics(_) => 6,
+            MyError::Io(_) | MyError::File { .. } | MyError::Database(_) => 7,
+        }
+    }
+}
+
+fn join_ids(ids: &[u64]) -> String {
+    ids.iter()
+        .map(|id| id.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
 fn main() {
     let options = Cli::parse();
 
+    if let Err(error) = run(options) {
+        eprintln!("エラー: {}", error);
+        std::process::exit(error.exit_code());
+    }
+}
+
+fn run(options: Cli) -> Result<(), MyError> {
+    let data_dir = DataDir::new(options.data_dir);
+    let name = options.calendar;
+    let path = data_dir.calendar_path(&name);
+    let store = backend::open(&path);
+    let now = options.now.unwrap_or_else(Utc::now);
+
     match options.command {
-        Commands::List => match read_calendar() {
-            Ok(calendar) => {
-                show_list(&calendar);
+        Commands::List {
+            from,
+            to,
+            today,
+            week,
+            subject,
+            regex,
+            sort,
+            format,
+            tag,
+            attendee,
+            tz,
+            merge,
+        } => {
+            let display_zone = tz.unwrap_or_else(time_zone::local_time_zone);
+            let current_date = time_zone::to_local(display_zone, now).date();
+            let (from, to) = if today {
+                let (from, to) = list::today_range(current_date, display_zone);
+                (Some(from), Some(to))
+            } else if week {
+                let (from, to) = list::week_range(current_date, display_zone);
+                (Some(from), Some(to))
+            } else {
+                (
+                    from.map(|from| time_zone::to_utc(display_zone, from)),
+                    to.map(|to| time_zone::to_utc(display_zone, to)),
+                )
+            };
+
+            let calendar = load_between(store.as_ref(), from, to)?;
+            let mut calendars = vec![(name.clone(), calendar)];
+            if merge {
+                calendars.extend(read_subscribed(&data_dir, &name)?);
             }
-            Err(error) => {
-                println!("カレンダーの読み込みに失敗しました");
+            let filter = list::Filter {
+                contains: subject,
+                pattern: regex,
+                tags: trim_all(&tag),
+                attendees: trim_all(&attendee),
+            };
+            let mut entries: Vec<_> = calendars
+                .into_iter()
+                .flat_map(|(calendar_name, calendar)| {
+                    let source = merge.then_some(calendar_name);
+                    expand_schedules(&calendar, from, to, now)
+                        .into_iter()
+                        .filter(|schedule| filter.matches(schedule))
+                        .map(move |schedule| list::Entry {
+                            calendar: source.clone(),
+                            schedule,
+                        })
+                })
+                .collect();
+            // まとめて表示するときは、指定が無くても開始順に並べる
+            match (sort, merge) {
+                (Some(key), _) => list::sort_entries(&mut entries, key),
+                (None, true) => list::sort_entries(&mut entries, SortKey::Start),
+                (None, false) => {}
             }
-        },
+
+            print!("{}", list::render(&entries, format, display_zone));
+        }
         Commands::Add {
             subject,
             start,
             end,
+            duration,
+            tz,
+            recurrence,
+            details,
+            status,
+            reminders,
+            check_subscribed,
+            per_attendee,
+            allow_overlap,
         } => {
-            let mut calendar = read_calendar().unwrap();
+            let others: Vec<_> = if check_subscribed {
+                read_subscribed(&data_dir, &name)?
+                    .into_iter()
+                    .map(|(_, calendar)| calendar)
+                    .collect()
+            } else {
+                vec![]
+            };
+            let time_zone = tz.unwrap_or_else(time_zone::local_time_zone);
+            let local_now = time_zone::to_local(time_zone, now);
+            let (start, end, all_day) = parse_range(&start, end.as_deref(), duration, local_now)?;
+            let new_schedule = Schedule {
+                id: 0,
+                subject,
+                details: details.to_details(),
+                start,
+                end,
+                all_day,
+                time_zone,
+                recurrence: recurrence.to_recurrence(),
+                uid: None,
+                reminders,
+                status,
+            };
 
-            if add_schedule(&mut calendar, subject, start, end) {
-                save_calendar(&mut calendar);
-                println!("予定を追加しました");
+            let policy = OverlapPolicy {
+                per_attendee,
+                allow_overlap,
+            };
+            store_new_schedule(&path, new_schedule, &others, &policy, now)?;
+            println!("予定を追加しました");
+        }
+        Commands::Delete { id } => {
+            store_deletion(&path, id, now)?;
+            println!("予定を削除しました");
+        }
+        Commands::Edit {
+            id,
+            subject,
+            start,
+            end,
+            shift,
+            tz,
+            reminders,
+            no_remind,
+            location,
+            description,
+            attendees,
+            no_attendee,
+            tags,
+            no_tag,
+            status,
+            check_subscribed,
+            per_attendee,
+            allow_overlap,
+        } => {
+            let others: Vec<_> = if check_subscribed {
+                read_subscribed(&data_dir, &name)?
+                    .into_iter()
+                    .map(|(_, calendar)| calendar)
+                    .collect()
             } else {
-                println!("エラー: 予定が重複しています");
+                vec![]
+            };
+            let local_now = time_zone::to_local(tz.unwrap_or_else(time_zone::local_time_zone), now);
+            let range = parse_new_range(start.as_deref(), end.as_deref(), local_now)?;
+            let changes = ScheduleChanges {
+                subject,
+                start: range.start,
+                end: range.end,
+                end_time: range.end_time,
+                all_day: range.all_day,
+                shift,
+                time_zone: tz,
+                reminders: if no_remind {
+                    Some(vec![])
+                } else {
+                    Some(reminders).filter(|reminders| !reminders.is_empty())
+                },
+                location,
+                description,
+                attendees: replacement(trim_all(&attendees), no_attendee),
+                tags: replacement(trim_all(&tags), no_tag),
+                status,
+            };
+
+            let policy = OverlapPolicy {
+                per_attendee,
+                allow_overlap,
+            };
+            store_edit(&path, id, &changes, &others, &policy, now)?;
+            println!("予定を変更しました");
+        }
+        Commands::Undo => match store_undo(&path, true, now)? {
+            Some(record) => println!(
+                "{}を取り消しました: {} {}",
+                record.action.name(),
+                record.id,
+                record.subject()
+            ),
+            None => println!("取り消せる操作はありません"),
+        },
+        Commands::Redo => match store_undo(&path, false, now)? {
+            Some(record) => println!(
+                "{}をやり直しました: {} {}",
+                record.action.name(),
+                record.id,
+                record.subject()
+            ),
+            None => println!("やり直せる操作はありません"),
+        },
+        Commands::History { limit, tz } => {
+            let display_zone = tz.unwrap_or_else(time_zone::local_time_zone);
+            let records = history::read(&path)?;
+            let undone = history::stacks(&records).undone;
+            if records.is_empty() {
+                println!("操作の記録はありません");
+            }
+            for (index, record) in records
+                .iter()
+                .enumerate()
+                .skip(records.len().saturating_sub(limit))
+            {
+                println!(
+                    "{} {} {} {}{}",
+                    time_zone::to_local(display_zone, record.time).format("%Y-%m-%d %H:%M:%S"),
+                    record.action.name(),
+                    record.id,
+                    record.subject(),
+                    if undone.contains(&index) {
+                        " (取り消し済み)"
+                    } else {
+                        ""
+                    }
+                );
             }
         }
-        Commands::Delete { id } => {
-            let mut calendar = read_calendar().unwrap();
+        Commands::Import {
+            path: ics_path,
+            allow_overlap,
+        } => {
+            let count = store_import(&path, &ics_path, allow_overlap, now)?;
+            println!("{}件の予定を取り込みました", count);
+        }
+        Commands::Export { path: ics_path } => {
+            let calendar = store.load()?;
+            let ics = ics::to_ics(&calendar);
+
+            match ics_path {
+                Some(ics_path) => {
+                    fs::write(&ics_path, ics).map_err(|source| MyError::File {
+                        path: ics_path,
+                        source,
+                    })?;
+                    println!("予定を書き出しました");
+                }
+                None => print!("{}", ics),
+            }
+        }
+        Commands::Free {
+            from,
+            to,
+            work_start,
+            work_end,
+            min_duration,
+            tz,
+            json,
+            merge,
+        } => {
+            if work_end <= work_start {
+                return Err(MyError::InvalidRange {
+                    start: work_start.to_string(),
+                    end: work_end.to_string(),
+                });
+            }
+
+            let time_zone = tz.unwrap_or_else(time_zone::local_time_zone);
+            let from = from.unwrap_or_else(|| time_zone::to_local(time_zone, now).date());
+            let to = to.unwrap_or(from + TimeDelta::days(FREE_SEARCH_DAYS));
+            if to < from {
+                return Err(MyError::InvalidRange {
+                    start: from.to_string(),
+                    end: to.to_string(),
+                });
+            }
+
+            let mut calendar = store.load_range(
+                time_zone::to_utc(time_zone, from.and_time(NaiveTime::MIN)),
+                time_zone::to_utc(time_zone, to.and_time(NaiveTime::MIN)) + TimeDelta::days(1),
+            )?;
+            if merge {
+                // 空き時間を調べるだけなので、予定をひとつのカレンダーに集める
+                for (_, other) in read_subscribed(&data_dir, &name)? {
+                    calendar.schedules.extend(other.schedules);
+                }
+            }
+            let query = free::FreeQuery {
+                from,
+                to,
+                work_start,
+                work_end,
+                min_duration,
+                time_zone,
+                now,
+            };
+            let slots = free::find_free_slots(&calendar, &query);
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&slots)?);
+            } else {
+                println!("START\tEND\tMINUTES");
+                for slot in slots {
+                    println!(
+                        "{}\t{}\t{}",
+                        slot.start.naive_local(),
+                        slot.end.naive_local(),
+                        slot.minutes
+                    );
+                }
+            }
+        }
+        Commands::Conflicts { per_attendee, tz } => {
+            let calendar = store.load()?;
+            let display_zone = tz.unwrap_or_else(time_zone::local_time_zone);
+            let policy = OverlapPolicy {
+                per_attendee,
+                ..Default::default()
+            };
 
-            if delete_schedule(&mut calendar, id) {
-                save_calendar(&mut calendar);
-                println!("予定を削除しました");
+            let conflicts = list_conflicts(&calendar, &policy);
+            if conflicts.is_empty() {
+                println!("重なっている予定はありません");
+            }
+            for (a, b, from, to) in conflicts {
+                println!(
+                    "{} {} と {} {}: {} - {}",
+                    a.id,
+                    a.subject,
+                    b.id,
+                    b.subject,
+                    time_zone::to_local(display_zone, from),
+                    time_zone::to_local(display_zone, to)
+                );
+            }
+        }
+        Commands::Stats {
+            from,
+            to,
+            by,
+            keywords,
+            work_start,
+            work_end,
+            top,
+            format,
+            tz,
+            merge,
+        } => {
+            if work_end <= work_start {
+                return Err(MyError::InvalidRange {
+                    start: work_start.to_string(),
+                    end: work_end.to_string(),
+                });
+            }
+            let time_zone = tz.unwrap_or_else(time_zone::local_time_zone);
+            let current_date = time_zone::to_local(time_zone, now).date();
+            let from = from.unwrap_or_else(|| stats::monday_of(current_date));
+            let to = to.unwrap_or(from + TimeDelta::days(6));
+            if to < from {
+                return Err(MyError::InvalidRange {
+                    start: from.to_string(),
+                    end: to.to_string(),
+                });
+            }
+
+            let mut calendar = store.load_range(
+                time_zone::to_utc(time_zone, from.and_time(NaiveTime::MIN)) - TimeDelta::days(1),
+                time_zone::to_utc(time_zone, to.and_time(NaiveTime::MIN)) + TimeDelta::days(2),
+            )?;
+            if merge {
+                for (_, other) in read_subscribed(&data_dir, &name)? {
+                    calendar.schedules.extend(other.schedules);
+                }
+            }
+            let query = stats::StatsQuery {
+                from,
+                to,
+                by,
+                keywords: trim_all(&keywords),
+                work_start,
+                work_end,
+                time_zone,
+                top,
+            };
+            print!(
+                "{}",
+                stats::render(&stats::report(&calendar, &query), format)
+            );
+        }
+        Commands::New { name, backend } => {
+            data_dir.create_calendar(&name, backend)?;
+            println!("カレンダー {} を作成しました", name);
+        }
+        Commands::Migrate { to } => {
+            if BackendKind::of(&path) == to {
+                println!("カレンダー {} はすでにこの形式で保存されています", name);
+                return Ok(());
+            }
+            let target = data_dir.calendar_path_for(&name, to);
+            let _lock = lock_calendar(&path)?;
+            let calendar = store.load()?;
+            // 予定は変わらないので操作の記録には追記しない。記録のファイルは形式によらず同じものを使う
+            backend::open(&target).save(&calendar, Change::All)?;
+
+            // 両方の形式のファイルがあると新しい形式が使われるので、元のファイルは名前を変えて残す
+            let mut migrated = path.clone().into_os_string();
+            migrated.push(".migrated");
+            match fs::rename(&path, &migrated) {
+                Err(error) if error.kind() != ErrorKind::NotFound => {
+                    return Err(MyError::File {
+                        path: path.clone(),
+                        source: error,
+                    })
+                }
+                _ => {}
+            }
+            println!(
+                "{}件の予定を {} に移しました",
+                calendar.schedules.len(),
+                target.display()
+            );
+        }
+        Commands::Calendars => {
+            let subscriptions = data_dir.subscriptions()?;
+            println!("NAME\tSUBSCRIBED");
+            for calendar in data_dir.calendar_names()? {
+                let subscribed = if subscriptions.contains(&calendar) {
+                    "*"
+                } else {
+                    ""
+                };
+                println!("{}\t{}", calendar, subscribed);
+            }
+        }
+        Commands::Subscribe { name } => {
+            let mut subscriptions = data_dir.subscriptions()?;
+            if !data_dir.calendar_path(&name).exists() {
+                return Err(MyError::CalendarNotFound(name));
+            } else if subscriptions.contains(&name) {
+                println!("カレンダー {} はすでに購読しています", name);
+            } else {
+                subscriptions.push(name.clone());
+                data_dir.save_subscriptions(subscriptions)?;
+                println!("カレンダー {} を購読しました", name);
+            }
+        }
+        Commands::Unsubscribe { name } => {
+            let mut subscriptions = data_dir.subscriptions()?;
+            if subscriptions.contains(&name) {
+                subscriptions.retain(|subscription| *subscription != name);
+                data_dir.save_subscriptions(subscriptions)?;
+                println!("カレンダー {} の購読をやめました", name);
             } else {
-                println!("エラー: IDが不正です");
+                println!("カレンダー {} は購読していません", name);
+            }
+        }
+        Commands::View {
+            span,
+            date,
+            tz,
+            merge,
+        } => {
+            let zone = tz.unwrap_or_else(time_zone::local_time_zone);
+            let local_now = time_zone::to_local(zone, now);
+            let date = match date {
+                Some(text) => when::parse_when(&text, local_now)
+                    .map_err(MyError::Usage)?
+                    .start()
+                    .date(),
+                None => local_now.date(),
+            };
+            let grid = view::Grid {
+                span,
+                date,
+                today: local_now.date(),
+                zone,
+                color: std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+            };
+
+            // 終日の予定はタイムゾーンを変換しないので、前後に1日ずつ広げて展開する
+            let (first, last) = grid.range();
+            let from = time_zone::to_utc(zone, first.and_time(NaiveTime::MIN)) - TimeDelta::days(1);
+            let to = time_zone::to_utc(zone, last.and_time(NaiveTime::MIN)) + TimeDelta::days(2);
+            let mut calendar = store.load_range(from, to)?;
+            if merge {
+                for (_, other) in read_subscribed(&data_dir, &name)? {
+                    calendar.schedules.extend(other.schedules);
+                }
             }
+            print!("{}", grid.render(&expand_between(&calendar, from, to)));
+        }
+        Commands::Watch {
+            sink,
+            notify_command,
+            webhook,
+            interval,
+            merge,
+        } => {
+            let zone = time_zone::local_time_zone();
+            let sink: Box<dyn watch::Sink> = match (sink, webhook) {
+                (watch::SinkKind::Stdout, _) => Box::new(watch::StdoutSink { zone }),
+                (watch::SinkKind::Notify, _) => Box::new(watch::CommandSink {
+                    program: notify_command,
+                    zone,
+                }),
+                (watch::SinkKind::Webhook, Some(url)) => Box::new(watch::WebhookSink { url }),
+                // clap の required_if_eq で webhook の URL は必ずある
+                (watch::SinkKind::Webhook, None) => unreachable!(),
+            };
+
+            let mut sources = if merge {
+                let mut names = vec![name.clone()];
+                names.extend(
+                    data_dir
+                        .subscriptions()?
+                        .into_iter()
+                        .filter(|subscription| *subscription != name),
+                );
+                names
+                    .into_iter()
+                    .map(|name| {
+                        let path = data_dir.calendar_path(&name);
+                        watch::Source::new(Some(name), path)
+                    })
+                    .collect()
+            } else {
+                vec![watch::Source::new(None, path)]
+            };
+            watch::run(&mut sources, sink.as_ref(), interval)?;
+        }
+        Commands::Serve { port, tz } => {
+            // 外からは使えないよう、ループバックだけで待ち受ける
+            let listener = std::net::TcpListener::bind(("127.0.0.1", port))?;
+            println!("http://{}/ で待っています", listener.local_addr()?);
+            std::io::stdout().flush()?;
+            let server = server::Server {
+                path,
+                zone: tz.unwrap_or_else(time_zone::local_time_zone),
+                now: options.now,
+            };
+            server.run(listener)?;
         }
     }
+    Ok(())
+}
+
+/// 期間の指定があれば、その期間にかかる予定だけを読む
+fn load_between(
+    store: &dyn backend::Backend,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+) -> Result<Calendar, MyError> {
+    match (from, to) {
+        (None, None) => store.load(),
+        (from, to) => store.load_range(
+            from.unwrap_or(DateTime::<Utc>::MIN_UTC),
+            to.unwrap_or(DateTime::<Utc>::MAX_UTC),
+        ),
+    }
+}
+
+/// 開始と、終了または長さの指定から予定の期間を決める。日付だけの指定なら終日の予定
+fn parse_range(
+    start: &str,
+    end: Option<&str>,
+    duration: Option<TimeDelta>,
+    local_now: NaiveDateTime,
+) -> Result<(NaiveDateTime, NaiveDateTime, bool), MyError> {
+    let start = when::parse_when(start, local_now).map_err(MyError::Usage)?;
+    let end = match (end, duration) {
+        (Some(end), _) => {
+            Some(when::parse_end(end, start.start(), local_now).map_err(MyError::Usage)?)
+        }
+        (None, Some(duration)) => Some(End::After(duration)),
+        (None, None) => None,
+    };
+    schedule_range(start, end)
+}
+
+/// Edit で指定された新しい開始と終了。None は変更しない
+#[derive(Debug, Default, PartialEq, Eq)]
+struct NewRange {
+    start: Option<NaiveDateTime>,
+    end: Option<NaiveDateTime>,
+    /// 開始を変えずに終了の時刻だけを指定された場合。予定の開始の日の時刻にする
+    end_time: Option<NaiveTime>,
+    /// 終日かどうかも指定に合わせて変える
+    all_day: Option<bool>,
+}
+
+fn parse_new_range(
+    start: Option<&str>,
+    end: Option<&str>,
+    local_now: NaiveDateTime,
+) -> Result<NewRange, MyError> {
+    let parse = |text: Option<&str>| {
+        text.map(|text| when::parse_when(text, local_now))
+            .transpose()
+            .map_err(MyError::Usage)
+    };
+    let start = parse(start)?;
+    let end_time = end.and_then(when::parse_time_only);
+    let end = match (start, end_time) {
+        // 時刻だけの終了は、新しい開始の日に合わせる
+        (Some(start), Some(time)) => Some(When::DateTime(when::time_after(start.start(), time))),
+        (None, Some(_)) => None,
+        (_, None) => parse(end)?,
+    };
+    let all_day = match (start, end) {
+        (Some(start), Some(end)) if start.is_date() != end.is_date() => {
+            return Err(mixed_range_error())
+        }
+        (Some(when), _) | (None, Some(when)) => Some(when.is_date()),
+        (None, None) if end_time.is_some() => Some(false),
+        (None, None) => None,
+    };
+    Ok(NewRange {
+        start: start.map(When::start),
+        end: end.map(When::end),
+        end_time: end_time.filter(|_| start.is_none()),
+        all_day,
+    })
 }
 
-fn read_calendar() -> Result<Calendar, std::io::Error> {
-    let file = File::open(SCHEDULE_FILE)?;
+// 以下の store_* はロックを取って読み込み、変更して保存する。CLI と Serve で共通
+// 予定の変更は操作の記録にも追記し、Undo で戻せるようにする
+
+fn store_new_schedule(
+    path: &Path,
+    new_schedule: Schedule,
+    others: &[Calendar],
+    policy: &OverlapPolicy,
+    now: DateTime<Utc>,
+) -> Result<u64, MyError> {
+    let _lock = lock_calendar(path)?;
+    let store = backend::open(path);
+    // 重なりを調べるのに必要な、新しい予定の期間にかかる予定だけを読む
+    let mut calendar = store.load_range(
+        new_schedule.start_utc(),
+        new_schedule.last_end().unwrap_or(DateTime::<Utc>::MAX_UTC),
+    )?;
+    let id = add_schedule(&mut calendar, new_schedule, others, policy)?;
+    store.save(&calendar, Change::Put(id))?;
+    history::append(
+        path,
+        &history::Record::new(now, history::Action::Add, None, calendar.get(id)),
+    )?;
+    Ok(id)
+}
+
+// 取り込んだ予定は1件ずつ追加として記録する
+fn store_import(
+    path: &Path,
+    ics_path: &PathBuf,
+    allow_overlap: bool,
+    now: DateTime<Utc>,
+) -> Result<usize, MyError> {
+    let _lock = lock_calendar(path)?;
+    let store = backend::open(path);
+    let mut calendar = store.load()?;
+    let ids = import_calendar(&mut calendar, ics_path, allow_overlap)?;
+    store.save(&calendar, Change::All)?;
+    for &id in &ids {
+        history::append(
+            path,
+            &history::Record::new(now, history::Action::Add, None, calendar.get(id)),
+        )?;
+    }
+    Ok(ids.len())
+}
+
+fn store_edit(
+    path: &Path,
+    id: u64,
+    changes: &ScheduleChanges,
+    others: &[Calendar],
+    policy: &OverlapPolicy,
+    now: DateTime<Utc>,
+) -> Result<(), MyError> {
+    let _lock = lock_calendar(path)?;
+    let store = backend::open(path);
+    let mut calendar = store.load()?;
+    let before = calendar.get(id).cloned();
+    edit_schedule(&mut calendar, id, changes, others, policy)?;
+    store.save(&calendar, Change::Put(id))?;
+    history::append(
+        path,
+        &history::Record::new(
+            now,
+            history::Action::Edit,
+            before.as_ref(),
+            calendar.get(id),
+        ),
+    )
+}
+
+fn store_deletion(path: &Path, id: u64, now: DateTime<Utc>) -> Result<(), MyError> {
+    let _lock = lock_calendar(path)?;
+    let store = backend::open(path);
+    let mut calendar = store.load()?;
+    let before = calendar.get(id).cloned().ok_or(MyError::NotFound(id))?;
+    delete_schedule(&mut calendar, id);
+    store.save(&calendar, Change::Delete(id))?;
+    history::append(
+        path,
+        &history::Record::new(now, history::Action::Delete, Some(&before), None),
+    )
+}
+
+/// 最後の操作を取り消す (undo) か、最後に取り消した操作をやり直す (redo)
+///
+/// 戻した操作を返す。対象の操作が無ければ None
+///
+/// 重なりはわざと調べない。操作は新しい順にしか戻せず、戻す予定が記録と違えば Stale になるので、
+/// 戻した後の予定は以前に実際にあった状態になる。--allow-overlap で重ねて追加した予定があると、
+/// 調べ直せば以前の状態にすら戻せなくなる
+fn store_undo(
+    path: &Path,
+    undo: bool,
+    now: DateTime<Utc>,
+) -> Result<Option<history::Record>, MyError> {
+    let _lock = lock_calendar(path)?;
+    let records = history::read(path)?;
+    let stacks = history::stacks(&records);
+    let target = if undo {
+        stacks.done.last()
+    } else {
+        stacks.undone.last()
+    };
+    let Some(record) = target.map(|&index| &records[index]) else {
+        return Ok(None);
+    };
+    let (from, to, action) = if undo {
+        (&record.after, &record.before, history::Action::Undo)
+    } else {
+        (&record.before, &record.after, history::Action::Redo)
+    };
+
+    let store = backend::open(path);
+    let mut calendar = store.load()?;
+    history::restore(&mut calendar, record.id, from.as_ref(), to.as_ref())?;
+    let change = if to.is_some() {
+        Change::Put(record.id)
+    } else {
+        Change::Delete(record.id)
+    };
+    store.save(&calendar, change)?;
+    history::append(
+        path,
+        &history::Record::new(now, action, from.as_ref(), to.as_ref()),
+    )?;
+    Ok(Some(record.clone()))
+}
+
+// 購読しているカレンダーのうち、current 以外を読み込む
+fn read_subscribed(data_dir: &DataDir, current: &str) -> Result<Vec<(String, Calendar)>, MyError> {
+    let mut calendars = Vec::new();
+    for name in data_dir.subscriptions()? {
+        if name == current {
+            continue;
+        }
+        let calendar = backend::open(&data_dir.calendar_path(&name)).load()?;
+        calendars.push((name, calendar));
+    }
+    Ok(calendars)
+}
+
+fn import_calendar(
+    calendar: &mut Calendar,
+    path: &PathBuf,
+    allow_overlap: bool,
+) -> Result<Vec<u64>, MyError> {
+    let text = fs::read_to_string(path).map_err(|source| MyError::File {
+        path: path.clone(),
+        source,
+    })?;
+    let parsed = ics::parse_ics(&text)?;
+
+    for error in &parsed.skipped {
+        println!("スキップ: {}", error);
+    }
+
+    let mut ids = Vec::new();
+    for schedule in parsed.schedules {
+        let uid = schedule.uid.clone();
+        if uid.is_some() && calendar.schedules.iter().any(|s| s.uid == uid) {
+            println!("スキップ: 取り込み済みの予定です: {}", schedule.subject);
+            continue;
+        }
+        if let Err(error) = schedule.check_range() {
+            println!("スキップ: {}: {}", schedule.subject, error);
+            continue;
+        }
+
+        let conflicts = find_conflicts(calendar, &schedule, &OverlapPolicy::default());
+        if !conflicts.is_empty() {
+            let ids: Vec<_> = conflicts.iter().map(|id| id.to_string()).collect();
+            println!(
+                "重複: {} ({}) は ID {} と重なっています",
+                schedule.subject,
+                schedule.start,
+                ids.join(", ")
+            );
+            if !allow_overlap {
+                continue;
+            }
+        }
+
+        ids.push(calendar.insert(schedule));
+    }
+    Ok(ids)
+}
+
+// ファイルがまだ無い場合は空のカレンダーとして扱い、最初の保存で作る
+fn read_calendar(path: &Path) -> Result<Calendar, MyError> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Calendar::default()),
+        Err(source) => {
+            return Err(MyError::File {
+                path: path.to_path_buf(),
+                source,
+            })
+        }
+    };
     let reader = BufReader::new(file);
-    let calendar = serde_json::from_reader(reader).unwrap();
+    let mut calendar: Calendar =
+        serde_json::from_reader(reader).map_err(|source| MyError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })?;
+    for (old, new) in calendar.repair_ids() {
+        eprintln!(
+            "重複していた ID {} の予定を ID {} に振り直しました",
+            old, new
+        );
+    }
     Ok(calendar)
 }
 
-fn save_calendar(calendar: &mut Calendar) -> Result<(), MyError> {
-    let file = File::create(SCHEDULE_FILE)?;
-    let writer = BufWriter::new(file);
-    serde_json::to_writer(writer, calendar)?;
-    Ok(())
+// 以前のファイルをバックアップに回してから、一時ファイル経由で置き換える
+fn save_calendar(path: &Path, calendar: &Calendar) -> Result<(), MyError> {
+    let file_error = |source| MyError::File {
+        path: path.to_path_buf(),
+        source,
+    };
+    storage::rotate_backups(path, storage::BACKUP_COUNT).map_err(file_error)?;
+    storage::write_atomic(path, |file| {
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, calendar)?;
+        // drop 時の flush ではエラーが分からないので明示的に書き出す
+        writer.flush()
+    })
+    .map_err(file_error)
+}
+
+// 読み込みから保存までの間、他のプロセスがカレンダーを書き換えないようにする
+fn lock_calendar(path: &Path) -> Result<FileLock, MyError> {
+    FileLock::acquire(path).map_err(|source| MyError::File {
+        path: path.to_path_buf(),
+        source,
+    })
 }
 
-fn show_list(calendar: &Calendar) {
-    println!("ID\tSTART\tEND\tSUBJECT");
+// 繰り返し予定を展開して、期間内の発生を予定ごとに並べる
+//
+// to が無い場合、終わりの無い繰り返しは now から DEFAULT_EXPAND_DAYS 日後までにする
+fn expand_schedules(
+    calendar: &Calendar,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> Vec<Schedule> {
+    let from = from.unwrap_or(DateTime::<Utc>::MIN_UTC);
+    let default_to = now + TimeDelta::days(DEFAULT_EXPAND_DAYS);
 
-    for schedule in &calendar.schedules {
-        println!(
-            "{}\t{}\t{}\t{}",
-            schedule.id, schedule.start, schedule.end, schedule.subject
-        );
+    calendar
+        .schedules
+        .iter()
+        .flat_map(|schedule| {
+            let unbounded = schedule
+                .recurrence
+                .as_ref()
+                .is_some_and(Recurrence::is_unbounded);
+            let to = match to {
+                Some(to) => to,
+                None if unbounded => default_to,
+                None => DateTime::<Utc>::MAX_UTC,
+            };
+            schedule.occurrences(from, to)
+        })
+        .collect()
+}
+
+// from から to までの発生を予定ごとに並べる
+fn expand_between(calendar: &Calendar, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<Schedule> {
+    calendar
+        .schedules
+        .iter()
+        .flat_map(|schedule| schedule.occurrences(from, to))
+        .collect()
+}
+
+// コマンドラインの開始と終了から (開始, 終了, 終日かどうか) を決める
+fn schedule_range(
+    start: When,
+    end: Option<End>,
+) -> Result<(NaiveDateTime, NaiveDateTime, bool), MyError> {
+    match (start, end) {
+        (When::Date(_), None) => Ok((start.start(), start.end(), true)),
+        (When::Date(_), Some(End::At(end @ When::Date(_)))) => Ok((start.start(), end.end(), true)),
+        (When::Date(_), Some(End::After(duration))) => {
+            if duration.num_seconds() % TimeDelta::days(1).num_seconds() != 0 {
+                return Err(MyError::Usage(
+                    "終日の予定の長さは日単位で指定してください (例: 2d)".to_string(),
+                ));
+            }
+            Ok((start.start(), add_duration(start.start(), duration)?, true))
+        }
+        (When::DateTime(start), Some(End::At(When::DateTime(end)))) => Ok((start, end, false)),
+        (When::DateTime(start), Some(End::After(duration))) => {
+            Ok((start, add_duration(start, duration)?, false))
+        }
+        (When::DateTime(_), None) => Err(MyError::Usage(
+            "終了日時か --duration を指定してください".to_string(),
+        )),
+        _ => Err(mixed_range_error()),
     }
 }
 
+// 開始からの長さで終了を求める。日時として表せなければ使い方の誤り
+fn add_duration(start: NaiveDateTime, duration: TimeDelta) -> Result<NaiveDateTime, MyError> {
+    start.checked_add_signed(duration).ok_or_else(|| {
+        MyError::Usage(format!(
+            "終了日時を表せません: {} から {}",
+            start,
+            duration::format_duration(duration)
+        ))
+    })
+}
+
+fn mixed_range_error() -> MyError {
+    MyError::Usage("開始と終了は、両方とも日付か両方とも日時で指定してください".to_string())
+}
+
+// 予定に新しい ID を振って追加し、その ID を返す。others のカレンダーの予定とも重なる場合は追加しない
 fn add_schedule(
     calendar: &mut Calendar,
-    subject: String,
-    start: NaiveDateTime,
-    end: NaiveDateTime,
-) -> bool {
-    let id = calendar.schedules.len() as u64;
-    let new_schedule = Schedule {
-        id,
-        subject,
-        start,
-        end,
-    };
+    new_schedule: Schedule,
+    others: &[Calendar],
+    policy: &OverlapPolicy,
+) -> Result<u64, MyError> {
+    new_schedule.check_range()?;
+    let conflicts: Vec<_> = std::iter::once(&*calendar)
+        .chain(others)
+        .flat_map(|calendar| find_conflicts(calendar, &new_schedule, policy))
+        .collect();
+    if !conflicts.is_empty() && !policy.allow_overlap {
+        return Err(MyError::Conflict(conflicts));
+    }
 
-    for schedule in &calendar.schedules {
-        if schedule.intersects(&new_schedule) {
-            return false;
+    Ok(calendar.insert(new_schedule))
+}
+
+/// Edit で変更する項目。None の項目は変更しない
+#[derive(Default)]
+struct ScheduleChanges {
+    subject: Option<String>,
+    start: Option<NaiveDateTime>,
+    end: Option<NaiveDateTime>,
+    // 時刻だけ指定された終了。変更後の開始の日の時刻にする
+    end_time: Option<NaiveTime>,
+    all_day: Option<bool>,
+    shift: Option<TimeDelta>,
+    time_zone: Option<Tz>,
+    reminders: Option<Vec<TimeDelta>>,
+    // 空文字列なら消す
+    location: Option<String>,
+    description: Option<String>,
+    attendees: Option<Vec<String>>,
+    tags: Option<Vec<String>>,
+    status: Option<Status>,
+}
+
+// リストを置き換える変更。clear なら空にし、指定が無ければ変えない
+fn replacement(values: Vec<String>, clear: bool) -> Option<Vec<String>> {
+    if clear {
+        Some(vec![])
+    } else {
+        Some(values).filter(|values| !values.is_empty())
+    }
+}
+
+impl ScheduleChanges {
+    fn apply(&self, schedule: &mut Schedule) {
+        if let Some(subject) = &self.subject {
+            schedule.subject = subject.clone();
+        }
+        if let Some(time_zone) = self.time_zone {
+            schedule.time_zone = time_zone;
+        }
+        if let Some(reminders) = &self.reminders {
+            schedule.reminders = reminders.clone();
+        }
+        if let Some(all_day) = self.all_day {
+            schedule.all_day = all_day;
+        }
+        if let Some(status) = self.status {
+            schedule.status = status;
+        }
+        let details = &mut schedule.details;
+        if let Some(location) = &self.location {
+            details.location = Some(location.clone()).filter(|location| !location.is_empty());
+        }
+        if let Some(description) = &self.description {
+            details.description =
+                Some(description.clone()).filter(|description| !description.is_empty());
+        }
+        if let Some(attendees) = &self.attendees {
+            details.attendees = attendees.clone();
+        }
+        if let Some(tags) = &self.tags {
+            details.tags = tags.clone();
+        }
+
+        // 開始を指定された場合は、終了や繰り返しの日時も一緒に移動する
+        let shift = match self.start {
+            Some(start) => Some(start - schedule.start),
+            None => self.shift,
+        };
+        if let Some(shift) = shift {
+            schedule.start += shift;
+            schedule.end += shift;
+            if let Some(rule) = &mut schedule.recurrence {
+                if let Some(until) = &mut rule.until {
+                    *until += shift;
+                }
+                for exdate in &mut rule.exdates {
+                    *exdate += shift;
+                }
+            }
+        }
+        if let Some(end) = self.end {
+            schedule.end = end;
+        } else if let Some(time) = self.end_time {
+            schedule.end = when::time_after(schedule.start, time);
+        }
+
+        // 終日の予定に変えるときは、0時から始めて長さを日単位に切り上げる
+        if self.all_day == Some(true) {
+            schedule.start = schedule.start.date().and_hms_opt(0, 0, 0).unwrap();
+            if self.end.is_none() {
+                let seconds = (schedule.end - schedule.start).num_seconds();
+                let days = (seconds + 86399) / 86400;
+                schedule.end = schedule.start + TimeDelta::days(days.max(1));
+            }
+        }
+    }
+}
+
+// 予定を変更する。自分以外の予定 (others のカレンダーも含む) と重なる場合は変更しない
+fn edit_schedule(
+    calendar: &mut Calendar,
+    id: u64,
+    changes: &ScheduleChanges,
+    others: &[Calendar],
+    policy: &OverlapPolicy,
+) -> Result<(), MyError> {
+    let index = calendar
+        .schedules
+        .iter()
+        .position(|schedule| schedule.id == id)
+        .ok_or(MyError::NotFound(id))?;
+
+    let mut edited = calendar.schedules[index].clone();
+    changes.apply(&mut edited);
+    edited.check_range()?;
+
+    let mut conflicts: Vec<_> = find_conflicts(calendar, &edited, policy)
+        .into_iter()
+        .filter(|other| *other != id)
+        .collect();
+    for other in others {
+        conflicts.extend(find_conflicts(other, &edited, policy));
+    }
+    if !conflicts.is_empty() && !policy.allow_overlap {
+        return Err(MyError::Conflict(conflicts));
+    }
+
+    calendar.schedules[index] = edited;
+    Ok(())
+}
+
+// カレンダーの中で重なっている予定の組と、最初に重なる期間を返す
+fn list_conflicts<'a>(
+    calendar: &'a Calendar,
+    policy: &OverlapPolicy,
+) -> Vec<(&'a Schedule, &'a Schedule, DateTime<Utc>, DateTime<Utc>)> {
+    let schedules = &calendar.schedules;
+    let mut conflicts = Vec::new();
+    for (i, a) in schedules.iter().enumerate() {
+        for b in &schedules[i + 1..] {
+            if let Some((from, to)) = policy.overlap(a, b) {
+                conflicts.push((a, b, from, to));
+            }
         }
     }
+    conflicts.sort_by_key(|(a, b, from, _)| (*from, a.id, b.id));
+    conflicts
+}
 
-    calendar.schedules.push(new_schedule);
-    true
+// 新しい予定と重なる既存の予定の ID を返す
+fn find_conflicts(
+    calendar: &Calendar,
+    new_schedule: &Schedule,
+    policy: &OverlapPolicy,
+) -> Vec<u64> {
+    calendar
+        .schedules
+        .iter()
+        .filter(|schedule| policy.conflicts(schedule, new_schedule))
+        .map(|schedule| schedule.id)
+        .collect()
 }
 
 fn delete_schedule(calendar: &mut Calendar, id: u64) -> bool {
-    for i in 0..calendar.schedules.len() {
-        if calendar.schedules[i].id == id {
-            calendar.schedules.remove(i);
-            return true;
+    // 読み込み時に ID の重複を解消しているので、削除されるのは高々1件
+    match calendar
+        .schedules
+        .iter()
+        .position(|schedule| schedule.id == id)
+    {
+        Some(index) => {
+            calendar.schedules.remove(index);
+            true
         }
+        None => false,
     }
-    false
 }
 
 #[cfg(test)]
@@ -159,7 +1937,6 @@ mod tests {
     use std::vec;
 
     use super::*;
-    use chrono::naive;
     use rstest::rstest;
 
     fn naive_date_time(
@@ -190,62 +1967,758 @@ mod tests {
         #[case] m1: u32,
         #[case] should_intersect: bool,
     ) {
-        let schedule = Schedule {
-            id: 0,
-            subject: "既存予定".to_string(),
-            start: naive_date_time(2024, 1, 1, h0, m0, 0),
-            end: naive_date_time(2024, 1, 1, h1, m1, 0),
+        let schedule = Schedule::test(
+            0,
+            "既存予定",
+            naive_date_time(2024, 1, 1, h0, m0, 0),
+            naive_date_time(2024, 1, 1, h1, m1, 0),
+        );
+        let new_schedule = Schedule::test(
+            999,
+            "新規予定",
+            naive_date_time(2024, 1, 1, 19, 0, 0),
+            naive_date_time(2024, 1, 1, 20, 0, 0),
+        );
+        assert_eq!(should_intersect, schedule.intersects(&new_schedule));
+    }
+
+    fn weekly_standup() -> Schedule {
+        Schedule {
+            recurrence: Some(Recurrence {
+                by_weekday: vec![Weekday::Mon, Weekday::Thu],
+                ..Recurrence::new(Frequency::Weekly)
+            }),
+            ..Schedule::test(
+                0,
+                "定例",
+                naive_date_time(2024, 1, 1, 10, 0, 0),
+                naive_date_time(2024, 1, 1, 10, 30, 0),
+            )
+        }
+    }
+
+    #[rstest]
+    #[case(
+        naive_date_time(2024, 3, 7, 10, 15, 0),
+        naive_date_time(2024, 3, 7, 11, 0, 0),
+        true
+    )]
+    #[case(
+        naive_date_time(2024, 3, 6, 10, 0, 0),
+        naive_date_time(2024, 3, 6, 11, 0, 0),
+        false
+    )]
+    #[case(
+        naive_date_time(2024, 3, 4, 10, 30, 0),
+        naive_date_time(2024, 3, 4, 11, 0, 0),
+        false
+    )]
+    #[case(
+        naive_date_time(2023, 12, 28, 10, 0, 0),
+        naive_date_time(2023, 12, 28, 11, 0, 0),
+        false
+    )]
+    fn test_recurring_schedule_intersects(
+        #[case] start: NaiveDateTime,
+        #[case] end: NaiveDateTime,
+        #[case] should_intersect: bool,
+    ) {
+        let new_schedule = Schedule::test(999, "新規予定", start, end);
+        assert_eq!(should_intersect, weekly_standup().intersects(&new_schedule));
+        assert_eq!(should_intersect, new_schedule.intersects(&weekly_standup()));
+    }
+
+    #[test]
+    fn test_recurring_schedules_intersect_each_other() {
+        let monthly = Schedule {
+            recurrence: Some(Recurrence::new(Frequency::Monthly)),
+            ..Schedule::test(
+                1,
+                "月次報告",
+                naive_date_time(2024, 1, 24, 10, 0, 0),
+                naive_date_time(2024, 1, 24, 11, 0, 0),
+            )
         };
-        let new_schedule = Schedule {
-            id: 999,
-            subject: "新規予定".to_string(),
-            start: naive_date_time(2024, 1, 1, 19, 0, 0),
-            end: naive_date_time(2024, 1, 1, 20, 0, 0),
+        // 2024年6月24日は月曜日なので定例と重なる
+        assert!(weekly_standup().intersects(&monthly));
+
+        let excluded = Schedule {
+            recurrence: Some(Recurrence {
+                until: Some(naive_date_time(2024, 6, 1, 0, 0, 0)),
+                ..Recurrence::new(Frequency::Monthly)
+            }),
+            ..monthly
         };
-        assert_eq!(should_intersect, schedule.intersects(&new_schedule));
+        // 1月〜5月の24日は月曜日でも木曜日でもない
+        assert!(!weekly_standup().intersects(&excluded));
+    }
+
+    #[rstest]
+    #[case(
+        naive_date_time(2024, 1, 1, 11, 0, 0),
+        naive_date_time(2024, 1, 1, 10, 0, 0)
+    )]
+    #[case(
+        naive_date_time(2024, 1, 1, 10, 0, 0),
+        naive_date_time(2024, 1, 1, 10, 0, 0)
+    )]
+    fn test_add_schedule_rejects_invalid_range(
+        #[case] start: NaiveDateTime,
+        #[case] end: NaiveDateTime,
+    ) {
+        let mut calendar = Calendar::default();
+        let error = add_schedule(
+            &mut calendar,
+            Schedule::test(0, "逆転", start, end),
+            &[],
+            &OverlapPolicy::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(error, MyError::InvalidRange { .. }));
+        assert!(calendar.schedules.is_empty());
+    }
+
+    #[rstest]
+    #[case(When::Date(NaiveDate::from_ymd_opt(2024, 1, 1).unwrap()), None, Some((1, 2, true)))]
+    #[case(
+        When::Date(NaiveDate::from_ymd_opt(2024, 1, 1).unwrap()),
+        Some(End::At(When::Date(NaiveDate::from_ymd_opt(2024, 1, 3).unwrap()))),
+        Some((1, 4, true))
+    )]
+    #[case(
+        When::Date(NaiveDate::from_ymd_opt(2024, 1, 1).unwrap()),
+        Some(End::After(TimeDelta::days(2))),
+        Some((1, 3, true))
+    )]
+    #[case(
+        When::Date(NaiveDate::from_ymd_opt(2024, 1, 1).unwrap()),
+        Some(End::After(TimeDelta::hours(2))),
+        None
+    )]
+    #[case(
+        When::DateTime(naive_date_time(2024, 1, 1, 0, 0, 0)),
+        Some(End::At(When::DateTime(naive_date_time(2024, 1, 2, 0, 0, 0)))),
+        Some((1, 2, false))
+    )]
+    #[case(
+        When::DateTime(naive_date_time(2024, 1, 1, 0, 0, 0)),
+        Some(End::After(TimeDelta::days(1))),
+        Some((1, 2, false))
+    )]
+    #[case(When::DateTime(naive_date_time(2024, 1, 1, 0, 0, 0)), None, None)]
+    #[case(
+        When::Date(NaiveDate::from_ymd_opt(2024, 1, 1).unwrap()),
+        Some(End::At(When::DateTime(naive_date_time(2024, 1, 2, 0, 0, 0)))),
+        None
+    )]
+    fn test_schedule_range(
+        #[case] start: When,
+        #[case] end: Option<End>,
+        #[case] expected: Option<(u32, u32, bool)>,
+    ) {
+        let expected = expected.map(|(start, end, all_day)| {
+            (
+                naive_date_time(2024, 1, start, 0, 0, 0),
+                naive_date_time(2024, 1, end, 0, 0, 0),
+                all_day,
+            )
+        });
+        assert_eq!(expected, schedule_range(start, end).ok());
+    }
+
+    // 今を 2024-01-03 12:00 とする
+    #[rstest]
+    // 時刻だけの終了は、今日ではなく開始の日の時刻
+    #[case("tomorrow 15:00", "16:00", Some((4, 15, 4, 16)))]
+    // 開始より前の時刻なら翌日に終わる
+    #[case("23:00", "1:00", Some((3, 23, 4, 1)))]
+    // 終日の予定に時刻の終了は指定できない
+    #[case("tomorrow", "16:00", None)]
+    fn test_parse_range_time_only_end(
+        #[case] start: &str,
+        #[case] end: &str,
+        #[case] expected: Option<(u32, u32, u32, u32)>,
+    ) {
+        let local_now = naive_date_time(2024, 1, 3, 12, 0, 0);
+        let expected = expected.map(|(start_day, start_hour, end_day, end_hour)| {
+            (
+                naive_date_time(2024, 1, start_day, start_hour, 0, 0),
+                naive_date_time(2024, 1, end_day, end_hour, 0, 0),
+                false,
+            )
+        });
+        assert_eq!(
+            expected,
+            parse_range(start, Some(end), None, local_now).ok()
+        );
+    }
+
+    #[test]
+    fn test_parse_new_range_time_only_end() {
+        let local_now = naive_date_time(2024, 1, 3, 12, 0, 0);
+
+        let range = parse_new_range(Some("tomorrow 15:00"), Some("16:00"), local_now).unwrap();
+        assert_eq!(
+            NewRange {
+                start: Some(naive_date_time(2024, 1, 4, 15, 0, 0)),
+                end: Some(naive_date_time(2024, 1, 4, 16, 0, 0)),
+                end_time: None,
+                all_day: Some(false),
+            },
+            range
+        );
+
+        // 開始を変えない場合は、予定の開始の日に合わせるため時刻のまま渡す
+        let range = parse_new_range(None, Some("16:00"), local_now).unwrap();
+        assert_eq!(
+            NewRange {
+                end_time: NaiveTime::from_hms_opt(16, 0, 0),
+                all_day: Some(false),
+                ..Default::default()
+            },
+            range
+        );
+    }
+
+    #[test]
+    fn test_all_day_schedule_blocks_whole_days() {
+        let mut calendar = Calendar::default();
+        let holiday = Schedule {
+            all_day: true,
+            ..Schedule::test(
+                0,
+                "休暇",
+                naive_date_time(2024, 1, 1, 0, 0, 0),
+                naive_date_time(2024, 1, 3, 0, 0, 0),
+            )
+        };
+        assert!(add_schedule(&mut calendar, holiday, &[], &OverlapPolicy::default()).is_ok());
+
+        let on_second_day = Schedule::test(
+            0,
+            "打ち合わせ",
+            naive_date_time(2024, 1, 2, 23, 0, 0),
+            naive_date_time(2024, 1, 3, 1, 0, 0),
+        );
+        assert!(
+            add_schedule(&mut calendar, on_second_day, &[], &OverlapPolicy::default()).is_err()
+        );
+        let after = Schedule::test(
+            0,
+            "打ち合わせ",
+            naive_date_time(2024, 1, 3, 0, 0, 0),
+            naive_date_time(2024, 1, 3, 1, 0, 0),
+        );
+        assert!(add_schedule(&mut calendar, after, &[], &OverlapPolicy::default()).is_ok());
+    }
+
+    #[test]
+    fn test_add_schedule_checks_every_occurrence() {
+        let mut calendar = Calendar {
+            schedules: vec![weekly_standup()],
+            next_id: 1,
+        };
+        assert!(add_schedule(
+            &mut calendar,
+            Schedule::test(
+                0,
+                "衝突する予定",
+                naive_date_time(2025, 6, 2, 10, 0, 0),
+                naive_date_time(2025, 6, 2, 10, 15, 0)
+            ),
+            &[],
+            &OverlapPolicy::default(),
+        )
+        .is_err());
+        assert!(add_schedule(
+            &mut calendar,
+            Schedule::test(
+                0,
+                "空いている予定",
+                naive_date_time(2025, 6, 3, 10, 0, 0),
+                naive_date_time(2025, 6, 3, 10, 15, 0)
+            ),
+            &[],
+            &OverlapPolicy::default(),
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_expand_schedules() {
+        let calendar = Calendar {
+            schedules: vec![weekly_standup()],
+            next_id: 1,
+        };
+        let occurrences = expand_schedules(
+            &calendar,
+            Some(naive_date_time(2024, 1, 1, 12, 0, 0).and_utc()),
+            Some(naive_date_time(2024, 1, 12, 0, 0, 0).and_utc()),
+            Utc::now(),
+        );
+        let starts: Vec<_> = occurrences.iter().map(|schedule| schedule.start).collect();
+        assert_eq!(
+            vec![
+                naive_date_time(2024, 1, 4, 10, 0, 0),
+                naive_date_time(2024, 1, 8, 10, 0, 0),
+                naive_date_time(2024, 1, 11, 10, 0, 0),
+            ],
+            starts
+        );
+    }
+
+    #[test]
+    fn test_expand_unbounded_schedules_from_now() {
+        let calendar = Calendar {
+            schedules: vec![weekly_standup()],
+            next_id: 1,
+        };
+        // 終わりの無い繰り返しは、指定された今から 30 日後まで
+        let occurrences = expand_schedules(
+            &calendar,
+            None,
+            None,
+            naive_date_time(2024, 1, 1, 0, 0, 0).and_utc(),
+        );
+        assert_eq!(9, occurrences.len());
+        assert_eq!(
+            naive_date_time(2024, 1, 29, 10, 0, 0),
+            occurrences.last().unwrap().start
+        );
+    }
+
+    #[test]
+    fn test_intersects_with_huge_count() {
+        let daily = |subject: &str, hour: u32| Schedule {
+            recurrence: Some(Recurrence {
+                count: Some(4_000_000_000),
+                ..Recurrence::new(Frequency::Daily)
+            }),
+            ..Schedule::test(
+                0,
+                subject,
+                naive_date_time(2024, 1, 1, hour, 0, 0),
+                naive_date_time(2024, 1, 1, hour + 1, 0, 0),
+            )
+        };
+        let morning = daily("朝会", 9);
+        let evening = daily("夕会", 17);
+        let dentist = Schedule::test(
+            1,
+            "歯医者",
+            naive_date_time(2025, 6, 2, 9, 30, 0),
+            naive_date_time(2025, 6, 2, 10, 30, 0),
+        );
+
+        // 回数が多すぎる繰り返しは最後まで数えず、終わりのない繰り返しと同じ範囲だけ調べる
+        assert_eq!(None, morning.last_end());
+        assert!(!morning.intersects(&evening));
+        assert!(morning.intersects(&dentist));
+    }
+
+    #[rstest]
+    // 冬の Berlin 10:00 は Tokyo 18:00
+    #[case(naive_date_time(2024, 3, 25, 18, 0, 0), true)]
+    // 夏時間になると Berlin 10:00 は Tokyo 17:00
+    #[case(naive_date_time(2024, 4, 1, 17, 0, 0), true)]
+    #[case(naive_date_time(2024, 4, 1, 18, 0, 0), false)]
+    fn test_intersects_across_time_zones(
+        #[case] tokyo_start: NaiveDateTime,
+        #[case] should_intersect: bool,
+    ) {
+        let berlin_standup = Schedule {
+            time_zone: Tz::Europe__Berlin,
+            recurrence: Some(Recurrence::new(Frequency::Weekly)),
+            ..Schedule::test(
+                0,
+                "Berlin 定例",
+                naive_date_time(2024, 3, 18, 10, 0, 0),
+                naive_date_time(2024, 3, 18, 10, 30, 0),
+            )
+        };
+        let tokyo_meeting = Schedule {
+            time_zone: Tz::Asia__Tokyo,
+            ..Schedule::test(
+                1,
+                "Tokyo 打ち合わせ",
+                tokyo_start,
+                tokyo_start + TimeDelta::minutes(30),
+            )
+        };
+        assert_eq!(should_intersect, berlin_standup.intersects(&tokyo_meeting));
+    }
+
+    #[test]
+    fn test_read_schedule_without_time_zone() {
+        let json = r#"{"schedules":[{"id":1,"subject":"テスト予定2","start":"2023-12-08T09:00:00","end":"2023-12-08T10:30:00"}]}"#;
+        let calendar: Calendar = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            time_zone::local_time_zone(),
+            calendar.schedules[0].time_zone
+        );
+    }
+
+    #[test]
+    fn test_read_missing_calendar() {
+        let path = test_dir("missing").join("schedule.json");
+        let calendar = read_calendar(&path).unwrap();
+        assert!(calendar.schedules.is_empty());
+
+        // 保存するときにディレクトリも作る
+        save_calendar(&path, &calendar).unwrap();
+        assert!(path.exists());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_read_corrupted_calendar() {
+        let path = test_dir("corrupted.json");
+        fs::write(&path, "{\"schedules\": [").unwrap();
+        let error = read_calendar(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(error, MyError::Parse { .. }));
+        assert_eq!(6, error.exit_code());
+    }
+
+    #[test]
+    fn test_add_schedule_checks_other_calendars() {
+        let mut personal = Calendar::default();
+        let team = Calendar {
+            schedules: vec![weekly_standup()],
+            next_id: 1,
+        };
+        let conflicting = Schedule::test(
+            0,
+            "歯医者",
+            naive_date_time(2024, 1, 8, 10, 0, 0),
+            naive_date_time(2024, 1, 8, 11, 0, 0),
+        );
+
+        assert!(matches!(
+            add_schedule(&mut personal, conflicting.clone(), &[team], &OverlapPolicy::default()),
+            Err(MyError::Conflict(ids)) if ids == vec![0]
+        ));
+        assert!(personal.schedules.is_empty());
+        assert!(add_schedule(&mut personal, conflicting, &[], &OverlapPolicy::default()).is_ok());
+    }
+
+    #[rstest]
+    #[case(Status::Busy, Status::Busy, false, false)]
+    #[case(Status::Busy, Status::Tentative, false, true)]
+    #[case(Status::Free, Status::Busy, false, true)]
+    #[case(Status::Busy, Status::Busy, true, true)]
+    fn test_add_schedule_overlap_policy(
+        #[case] existing: Status,
+        #[case] new: Status,
+        #[case] allow_overlap: bool,
+        #[case] added: bool,
+    ) {
+        let mut calendar = Calendar {
+            schedules: vec![Schedule {
+                status: existing,
+                ..weekly_standup()
+            }],
+            next_id: 1,
+        };
+        let overlapping = Schedule {
+            status: new,
+            ..Schedule::test(
+                0,
+                "歯医者",
+                naive_date_time(2024, 1, 8, 10, 0, 0),
+                naive_date_time(2024, 1, 8, 11, 0, 0),
+            )
+        };
+        let policy = OverlapPolicy {
+            allow_overlap,
+            ..Default::default()
+        };
+
+        let result = add_schedule(&mut calendar, overlapping, &[], &policy);
+        assert_eq!(added, result.is_ok());
+        if !added {
+            assert!(matches!(result, Err(MyError::Conflict(ids)) if ids == vec![0]));
+        }
+    }
+
+    #[test]
+    fn test_list_conflicts() {
+        let mut calendar = Calendar::default();
+        for schedule in [
+            weekly_standup(),
+            Schedule::test(
+                0,
+                "歯医者",
+                naive_date_time(2024, 1, 15, 10, 15, 0),
+                naive_date_time(2024, 1, 15, 12, 0, 0),
+            ),
+            Schedule::test(
+                0,
+                "昼食",
+                naive_date_time(2024, 1, 15, 11, 0, 0),
+                naive_date_time(2024, 1, 15, 13, 0, 0),
+            ),
+            // 仮の予定はどれとも重ならない
+            Schedule {
+                status: Status::Tentative,
+                ..Schedule::test(
+                    0,
+                    "勉強会",
+                    naive_date_time(2024, 1, 8, 10, 0, 0),
+                    naive_date_time(2024, 1, 8, 11, 0, 0),
+                )
+            },
+        ] {
+            calendar.insert(schedule);
+        }
+
+        let conflicts: Vec<_> = list_conflicts(&calendar, &OverlapPolicy::default())
+            .into_iter()
+            .map(|(a, b, from, to)| (a.id, b.id, from, to))
+            .collect();
+        let utc = |hour, minute| {
+            time_zone::to_utc(Tz::UTC, naive_date_time(2024, 1, 15, hour, minute, 0))
+        };
+        assert_eq!(
+            vec![
+                (0, 1, utc(10, 15), utc(10, 30)),
+                (1, 2, utc(11, 0), utc(12, 0)),
+            ],
+            conflicts
+        );
+    }
+
+    #[rstest]
+    #[case(&[], &[], true)]
+    #[case(&["alice"], &["bob"], false)]
+    #[case(&["alice", "bob"], &["Bob"], true)]
+    #[case(&["alice"], &[], false)]
+    fn test_per_attendee_conflicts(
+        #[case] existing: &[&str],
+        #[case] new: &[&str],
+        #[case] conflicts: bool,
+    ) {
+        let with_attendees = |subject, attendees: &[&str]| {
+            let mut schedule = Schedule::test(
+                0,
+                subject,
+                naive_date_time(2024, 1, 1, 10, 0, 0),
+                naive_date_time(2024, 1, 1, 11, 0, 0),
+            );
+            schedule.details.attendees = attendees.iter().map(|name| name.to_string()).collect();
+            schedule
+        };
+        let mut calendar = Calendar::default();
+        let policy = OverlapPolicy {
+            per_attendee: true,
+            ..Default::default()
+        };
+        assert!(add_schedule(
+            &mut calendar,
+            with_attendees("面談", existing),
+            &[],
+            &policy
+        )
+        .is_ok());
+
+        let result = add_schedule(&mut calendar, with_attendees("面談2", new), &[], &policy);
+        assert_eq!(conflicts, result.is_err());
+        // 全体で調べる場合は参加者に関係なく重なる
+        assert!(find_conflicts(
+            &calendar,
+            &with_attendees("別件", new),
+            &OverlapPolicy::default()
+        )
+        .contains(&0));
+    }
+
+    #[rstest]
+    // 日付だけの開始を指定すると、その日の終日の予定になる
+    #[case(
+        ScheduleChanges {
+            start: Some(naive_date_time(2024, 1, 5, 0, 0, 0)),
+            all_day: Some(true),
+            ..Default::default()
+        },
+        Ok((naive_date_time(2024, 1, 5, 0, 0, 0), naive_date_time(2024, 1, 6, 0, 0, 0)))
+    )]
+    // 開始だけ指定すると長さを保ったまま移動する
+    #[case(
+        ScheduleChanges { start: Some(naive_date_time(2024, 1, 2, 13, 0, 0)), ..Default::default() },
+        Ok((naive_date_time(2024, 1, 2, 13, 0, 0), naive_date_time(2024, 1, 2, 14, 0, 0)))
+    )]
+    #[case(
+        ScheduleChanges { end: Some(naive_date_time(2024, 1, 2, 11, 30, 0)), ..Default::default() },
+        Ok((naive_date_time(2024, 1, 2, 10, 0, 0), naive_date_time(2024, 1, 2, 11, 30, 0)))
+    )]
+    // 時刻だけの終了は予定の開始の日の時刻
+    #[case(
+        ScheduleChanges { end_time: NaiveTime::from_hms_opt(11, 30, 0), ..Default::default() },
+        Ok((naive_date_time(2024, 1, 2, 10, 0, 0), naive_date_time(2024, 1, 2, 11, 30, 0)))
+    )]
+    #[case(
+        ScheduleChanges { shift: Some(TimeDelta::days(-1)), ..Default::default() },
+        Ok((naive_date_time(2024, 1, 1, 10, 0, 0), naive_date_time(2024, 1, 1, 11, 0, 0)))
+    )]
+    // 自分自身とは重ならない扱い
+    #[case(
+        ScheduleChanges { shift: Some(TimeDelta::minutes(30)), ..Default::default() },
+        Ok((naive_date_time(2024, 1, 2, 10, 30, 0), naive_date_time(2024, 1, 2, 11, 30, 0)))
+    )]
+    #[case(
+        ScheduleChanges { end: Some(naive_date_time(2024, 1, 2, 12, 30, 0)), ..Default::default() },
+        Err(vec![1])
+    )]
+    fn test_edit_schedule(
+        #[case] changes: ScheduleChanges,
+        #[case] expected: Result<(NaiveDateTime, NaiveDateTime), Vec<u64>>,
+    ) {
+        let mut calendar = Calendar::default();
+        calendar.insert(Schedule::test(
+            0,
+            "移動する予定",
+            naive_date_time(2024, 1, 2, 10, 0, 0),
+            naive_date_time(2024, 1, 2, 11, 0, 0),
+        ));
+        calendar.insert(Schedule::test(
+            0,
+            "昼の予定",
+            naive_date_time(2024, 1, 2, 12, 0, 0),
+            naive_date_time(2024, 1, 2, 13, 0, 0),
+        ));
+
+        let result = match edit_schedule(&mut calendar, 0, &changes, &[], &OverlapPolicy::default())
+        {
+            Ok(()) => Ok((calendar.schedules[0].start, calendar.schedules[0].end)),
+            Err(MyError::Conflict(ids)) => Err(ids),
+            Err(error) => panic!("{}", error),
+        };
+        assert_eq!(expected, result);
+        assert_eq!(0, calendar.schedules[0].id);
+    }
+
+    #[test]
+    fn test_edit_missing_schedule() {
+        let mut calendar = Calendar::default();
+        let changes = ScheduleChanges {
+            subject: Some("新しい件名".to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            edit_schedule(&mut calendar, 3, &changes, &[], &OverlapPolicy::default()),
+            Err(MyError::NotFound(3))
+        ));
+    }
+
+    #[test]
+    fn test_ids_are_not_reused_after_delete() {
+        let mut calendar = Calendar {
+            schedules: vec![],
+            next_id: 0,
+        };
+        for day in 1..=3 {
+            assert!(add_schedule(
+                &mut calendar,
+                Schedule::test(
+                    0,
+                    &format!("予定{}", day),
+                    naive_date_time(2024, 1, day, 10, 0, 0),
+                    naive_date_time(2024, 1, day, 11, 0, 0)
+                ),
+                &[],
+                &OverlapPolicy::default(),
+            )
+            .is_ok());
+        }
+        assert!(delete_schedule(&mut calendar, 0));
+        assert!(add_schedule(
+            &mut calendar,
+            Schedule::test(
+                0,
+                "予定4",
+                naive_date_time(2024, 1, 4, 10, 0, 0),
+                naive_date_time(2024, 1, 4, 11, 0, 0)
+            ),
+            &[],
+            &OverlapPolicy::default(),
+        )
+        .is_ok());
+
+        let ids: Vec<_> = calendar.schedules.iter().map(|s| s.id).collect();
+        assert_eq!(vec![1, 2, 3], ids);
+        assert_eq!(4, calendar.next_id);
+    }
+
+    #[test]
+    fn test_repair_ids() {
+        let schedule = |id: u64, day: u32| {
+            Schedule::test(
+                id,
+                &format!("予定{}", day),
+                naive_date_time(2024, 1, day, 10, 0, 0),
+                naive_date_time(2024, 1, day, 11, 0, 0),
+            )
+        };
+        // next_id の無い以前のファイルで、削除後の追加により ID が重複している
+        let mut calendar = Calendar {
+            schedules: vec![schedule(1, 1), schedule(2, 2), schedule(2, 3)],
+            next_id: 0,
+        };
+
+        assert_eq!(vec![(2, 3)], calendar.repair_ids());
+        let ids: Vec<_> = calendar.schedules.iter().map(|s| s.id).collect();
+        assert_eq!(vec![1, 2, 3], ids);
+        assert_eq!(4, calendar.next_id);
+
+        assert!(calendar.repair_ids().is_empty());
+        assert!(delete_schedule(&mut calendar, 2));
+        assert_eq!("予定3", calendar.schedules[1].subject);
     }
 
     #[test]
     fn test_delete_schedule() {
         let mut calendar = Calendar {
             schedules: vec![
-                Schedule {
-                    id: 0,
-                    subject: "テスト予定".to_string(),
-                    start: naive_date_time(2023, 11, 19, 11, 22, 33),
-                    end: naive_date_time(2023, 11, 19, 22, 33, 44),
-                },
-                Schedule {
-                    id: 1,
-                    subject: "テスト予定2".to_string(),
-                    start: naive_date_time(2023, 12, 8, 9, 0, 0),
-                    end: naive_date_time(2023, 12, 8, 19, 30, 0),
-                },
-                Schedule {
-                    id: 2,
-                    subject: "追加できる予定".to_string(),
-                    start: naive_date_time(2023, 12, 15, 10, 0, 0),
-                    end: naive_date_time(2023, 12, 15, 11, 00, 0),
-                },
+                Schedule::test(
+                    0,
+                    "テスト予定",
+                    naive_date_time(2023, 11, 19, 11, 22, 33),
+                    naive_date_time(2023, 11, 19, 22, 33, 44),
+                ),
+                Schedule::test(
+                    1,
+                    "テスト予定2",
+                    naive_date_time(2023, 12, 8, 9, 0, 0),
+                    naive_date_time(2023, 12, 8, 19, 30, 0),
+                ),
+                Schedule::test(
+                    2,
+                    "追加できる予定",
+                    naive_date_time(2023, 12, 15, 10, 0, 0),
+                    naive_date_time(2023, 12, 15, 11, 00, 0),
+                ),
             ],
+            next_id: 3,
         };
         assert!(delete_schedule(&mut calendar, 0));
 
         let expected = Calendar {
             schedules: vec![
-                Schedule {
-                    id: 1,
-                    subject: "テスト予定2".to_string(),
-                    start: naive_date_time(2023, 12, 8, 9, 0, 0),
-                    end: naive_date_time(2023, 12, 8, 19, 30, 0),
-                },
-                Schedule {
-                    id: 2,
-                    subject: "追加できる予定".to_string(),
-                    start: naive_date_time(2023, 12, 15, 10, 0, 0),
-                    end: naive_date_time(2023, 12, 15, 11, 00, 0),
-                },
+                Schedule::test(
+                    1,
+                    "テスト予定2",
+                    naive_date_time(2023, 12, 8, 9, 0, 0),
+                    naive_date_time(2023, 12, 8, 19, 30, 0),
+                ),
+                Schedule::test(
+                    2,
+                    "追加できる予定",
+                    naive_date_time(2023, 12, 15, 10, 0, 0),
+                    naive_date_time(2023, 12, 15, 11, 00, 0),
+                ),
             ],
+            next_id: 3,
         };
 
         assert_eq!(expected, calendar);
@@ -253,19 +2726,114 @@ mod tests {
         assert!(delete_schedule(&mut calendar, 1));
 
         let expected = Calendar {
-            schedules: vec![Schedule {
-                id: 2,
-                subject: "追加できる予定".to_string(),
-                start: naive_date_time(2023, 12, 15, 10, 0, 0),
-                end: naive_date_time(2023, 12, 15, 11, 00, 0),
-            }],
+            schedules: vec![Schedule::test(
+                2,
+                "追加できる予定",
+                naive_date_time(2023, 12, 15, 10, 0, 0),
+                naive_date_time(2023, 12, 15, 11, 00, 0),
+            )],
+            next_id: 3,
         };
         assert_eq!(expected, calendar);
 
         assert!(delete_schedule(&mut calendar, 2));
 
-        let expected = Calendar { schedules: vec![] };
+        let expected = Calendar {
+            schedules: vec![],
+            next_id: 3,
+        };
 
         assert_eq!(expected, calendar);
     }
+
+    #[test]
+    fn test_store_undo_restores_overlapping_schedule() {
+        let dir = test_dir("undo-overlap");
+        let path = dir.join("schedule.json");
+        let now = naive_date_time(2024, 1, 3, 3, 0, 0).and_utc();
+        let schedule = |subject: &str| {
+            Schedule::test(
+                0,
+                subject,
+                naive_date_time(2024, 1, 1, 10, 0, 0),
+                naive_date_time(2024, 1, 1, 11, 0, 0),
+            )
+        };
+        let allow = OverlapPolicy {
+            allow_overlap: true,
+            ..OverlapPolicy::default()
+        };
+
+        store_new_schedule(&path, schedule("朝会"), &[], &OverlapPolicy::default(), now).unwrap();
+        store_new_schedule(&path, schedule("面談"), &[], &allow, now).unwrap();
+        store_deletion(&path, 0, now).unwrap();
+        // 削除の取り消しで戻る予定は面談と重なるが、削除前の状態なので戻せる
+        let undone = store_undo(&path, true, now).map(|record| record.map(|r| r.action));
+        let restored = backend::open(&path).load().unwrap();
+        // やり直しも同じく重なりを調べずに削除する
+        let redone = store_undo(&path, false, now).map(|record| record.map(|r| r.action));
+        let calendar = backend::open(&path).load().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(matches!(undone, Ok(Some(history::Action::Delete))));
+        assert_eq!(2, restored.schedules.len());
+        assert!(matches!(redone, Ok(Some(history::Action::Delete))));
+        let subjects: Vec<_> = calendar
+            .schedules
+            .iter()
+            .map(|s| s.subject.as_str())
+            .collect();
+        assert_eq!(vec!["面談"], subjects);
+    }
+
+    #[test]
+    fn test_store_import_records_each_schedule() {
+        let dir = test_dir("import");
+        let path = dir.join("schedule.json");
+        let ics_path = dir.join("import.ics");
+        let now = naive_date_time(2024, 1, 3, 3, 0, 0).and_utc();
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            &ics_path,
+            "BEGIN:VEVENT\r\n\
+             UID:a\r\n\
+             DTSTART:20240101T090000Z\r\n\
+             DTEND:20240101T100000Z\r\n\
+             SUMMARY:朝会\r\n\
+             END:VEVENT\r\n\
+             BEGIN:VEVENT\r\n\
+             UID:b\r\n\
+             DTSTART:20240102T090000Z\r\n\
+             DTEND:20240102T100000Z\r\n\
+             SUMMARY:面談\r\n\
+             END:VEVENT\r\n",
+        )
+        .unwrap();
+
+        let count = store_import(&path, &ics_path, false, now).unwrap();
+        let records = history::read(&path).unwrap();
+        // 取り込みも1件ずつ取り消せる
+        store_undo(&path, true, now).unwrap();
+        let calendar = backend::open(&path).load().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(2, count);
+        let actions: Vec<_> = records
+            .iter()
+            .map(|record| (record.time, record.action, record.subject()))
+            .collect();
+        assert_eq!(
+            vec![
+                (now, history::Action::Add, "朝会"),
+                (now, history::Action::Add, "面談"),
+            ],
+            actions
+        );
+        let subjects: Vec<_> = calendar
+            .schedules
+            .iter()
+            .map(|s| s.subject.as_str())
+            .collect();
+        assert_eq!(vec!["朝会"], subjects);
+    }
 }
@@ -0,0 +1,284 @@
+use chrono::{Datelike, Days, Months, NaiveDate, NaiveDateTime, Weekday};
+use serde::{Deserialize, Serialize};
+
+/// 繰り返しの単位
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// RRULE 相当の繰り返しルール
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Recurrence {
+    pub frequency: Frequency,
+    #[serde(default = "default_interval")]
+    pub interval: u32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub by_weekday: Vec<Weekday>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub count: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<NaiveDateTime>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exdates: Vec<NaiveDateTime>,
+}
+
+fn default_interval() -> u32 {
+    1
+}
+
+impl Recurrence {
+    pub fn new(frequency: Frequency) -> Self {
+        Self {
+            frequency,
+            interval: 1,
+            by_weekday: Vec::new(),
+            count: None,
+            until: None,
+            exdates: Vec::new(),
+        }
+    }
+
+    /// count も until も無い場合は無限に繰り返す
+    pub fn is_unbounded(&self) -> bool {
+        self.count.is_none() && self.until.is_none()
+    }
+
+    /// dtstart から始まる発生日時を順に返すイテレータ
+    pub fn starts(&self, dtstart: NaiveDateTime) -> Starts<'_> {
+        Starts {
+            rule: self,
+            dtstart,
+            period: 0,
+            buffer: Vec::new(),
+            generated: 0,
+            finished: false,
+        }
+    }
+}
+
+pub struct Starts<'a> {
+    rule: &'a Recurrence,
+    dtstart: NaiveDateTime,
+    period: u32,
+    // 現在の周期内で未出力の候補 (降順に積んで pop で取り出す)
+    buffer: Vec<NaiveDateTime>,
+    generated: u32,
+    finished: bool,
+}
+
+impl Starts<'_> {
+    /// 次の周期の候補日時を計算する。周期の先頭が表現できない場合は None
+    fn next_period(&mut self) -> Option<Vec<NaiveDateTime>> {
+        let rule = self.rule;
+        let step = self.period.checked_mul(rule.interval.max(1))?;
+        self.period += 1;
+
+        let base = self.dtstart.date();
+        let time = self.dtstart.time();
+        let dates: Vec<NaiveDate> = match rule.frequency {
+            Frequency::Daily => {
+                // 間隔が 7 の倍数だと曜日が変わらないので、開始の曜日が by_weekday に無ければ二度と発生しない
+                let weekday_fixed = rule.interval.max(1).is_multiple_of(7);
+                if weekday_fixed
+                    && !rule.by_weekday.is_empty()
+                    && !rule.by_weekday.contains(&base.weekday())
+                {
+                    return None;
+                }
+                let date = base.checked_add_days(Days::new(step as u64))?;
+                if rule.by_weekday.is_empty() || rule.by_weekday.contains(&date.weekday()) {
+                    vec![date]
+                } else {
+                    vec![]
+                }
+            }
+            Frequency::Weekly => {
+                let week_start = base
+                    .checked_sub_days(Days::new(base.weekday().num_days_from_monday() as u64))?
+                    .checked_add_days(Days::new(step as u64 * 7))?;
+                if rule.by_weekday.is_empty() {
+                    vec![week_start.checked_add_days(Days::new(
+                        base.weekday().num_days_from_monday() as u64,
+                    ))?]
+                } else {
+                    (0..7)
+                        .filter_map(|offset| week_start.checked_add_days(Days::new(offset)))
+                        .filter(|date| rule.by_weekday.contains(&date.weekday()))
+                        .collect()
+                }
+            }
+            Frequency::Monthly => {
+                let month_start = base.with_day(1)?.checked_add_months(Months::new(step))?;
+                if rule.by_weekday.is_empty() {
+                    // 該当する日が無い月 (例: 31日) はスキップする
+                    month_start.with_day(base.day()).into_iter().collect()
+                } else {
+                    month_start
+                        .iter_days()
+                        .take_while(|date| date.month() == month_start.month())
+                        .filter(|date| rule.by_weekday.contains(&date.weekday()))
+                        .collect()
+                }
+            }
+            Frequency::Yearly => {
+                let year = base.year().checked_add(i32::try_from(step).ok()?)?;
+                if rule.by_weekday.is_empty() {
+                    // 2月29日はうるう年以外スキップする
+                    NaiveDate::from_ymd_opt(year, base.month(), base.day())
+                        .into_iter()
+                        .collect()
+                } else {
+                    let year_start = NaiveDate::from_ymd_opt(year, 1, 1)?;
+                    year_start
+                        .iter_days()
+                        .take_while(|date| date.year() == year)
+                        .filter(|date| rule.by_weekday.contains(&date.weekday()))
+                        .collect()
+                }
+            }
+        };
+
+        Some(
+            dates
+                .into_iter()
+                .map(|date| date.and_time(time))
+                .filter(|start| *start >= self.dtstart)
+                .collect(),
+        )
+    }
+}
+
+impl Iterator for Starts<'_> {
+    type Item = NaiveDateTime;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.finished {
+                return None;
+            }
+            if let Some(count) = self.rule.count {
+                if self.generated >= count {
+                    self.finished = true;
+                    return None;
+                }
+            }
+
+            let Some(start) = self.buffer.pop() else {
+                match self.next_period() {
+                    Some(mut candidates) => {
+                        candidates.reverse();
+                        self.buffer = candidates;
+                    }
+                    None => self.finished = true,
+                }
+                continue;
+            };
+
+            if let Some(until) = self.rule.until {
+                if start > until {
+                    self.finished = true;
+                    return None;
+                }
+            }
+
+            // COUNT は除外日を含めて数える (RFC 5545 と同じ)
+            self.generated += 1;
+            if self.rule.exdates.contains(&start) {
+                continue;
+            }
+            return Some(start);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn dt(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(year, month, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[rstest]
+    #[case(Frequency::Daily, 2, vec![], vec![dt(2024, 1, 1, 9, 0), dt(2024, 1, 3, 9, 0), dt(2024, 1, 5, 9, 0)])]
+    #[case(Frequency::Weekly, 1, vec![], vec![dt(2024, 1, 1, 9, 0), dt(2024, 1, 8, 9, 0), dt(2024, 1, 15, 9, 0)])]
+    #[case(Frequency::Weekly, 1, vec![Weekday::Mon, Weekday::Wed], vec![dt(2024, 1, 1, 9, 0), dt(2024, 1, 3, 9, 0), dt(2024, 1, 8, 9, 0)])]
+    #[case(Frequency::Monthly, 1, vec![], vec![dt(2024, 1, 1, 9, 0), dt(2024, 2, 1, 9, 0), dt(2024, 3, 1, 9, 0)])]
+    #[case(Frequency::Yearly, 1, vec![], vec![dt(2024, 1, 1, 9, 0), dt(2025, 1, 1, 9, 0), dt(2026, 1, 1, 9, 0)])]
+    fn test_starts(
+        #[case] frequency: Frequency,
+        #[case] interval: u32,
+        #[case] by_weekday: Vec<Weekday>,
+        #[case] expected: Vec<NaiveDateTime>,
+    ) {
+        let rule = Recurrence {
+            interval,
+            by_weekday,
+            count: Some(3),
+            ..Recurrence::new(frequency)
+        };
+        let starts: Vec<_> = rule.starts(dt(2024, 1, 1, 9, 0)).collect();
+        assert_eq!(expected, starts);
+    }
+
+    #[test]
+    fn test_monthly_skips_missing_days() {
+        let rule = Recurrence {
+            count: Some(3),
+            ..Recurrence::new(Frequency::Monthly)
+        };
+        let starts: Vec<_> = rule.starts(dt(2024, 1, 31, 9, 0)).collect();
+        assert_eq!(
+            vec![
+                dt(2024, 1, 31, 9, 0),
+                dt(2024, 3, 31, 9, 0),
+                dt(2024, 5, 31, 9, 0)
+            ],
+            starts
+        );
+    }
+
+    #[rstest]
+    #[case(vec![Weekday::Sat], vec![])]
+    #[case(vec![Weekday::Mon, Weekday::Sat], vec![dt(2024, 1, 1, 9, 0), dt(2024, 1, 8, 9, 0)])]
+    fn test_daily_every_week_by_weekday(
+        #[case] by_weekday: Vec<Weekday>,
+        #[case] expected: Vec<NaiveDateTime>,
+    ) {
+        // 2024年1月1日は月曜日。7日おきなら月曜日にしかならない
+        let rule = Recurrence {
+            interval: 7,
+            by_weekday,
+            ..Recurrence::new(Frequency::Daily)
+        };
+        let starts: Vec<_> = rule.starts(dt(2024, 1, 1, 9, 0)).take(2).collect();
+        assert_eq!(expected, starts);
+    }
+
+    #[test]
+    fn test_until_and_exdates() {
+        let rule = Recurrence {
+            until: Some(dt(2024, 1, 4, 9, 0)),
+            exdates: vec![dt(2024, 1, 2, 9, 0)],
+            ..Recurrence::new(Frequency::Daily)
+        };
+        let starts: Vec<_> = rule.starts(dt(2024, 1, 1, 9, 0)).collect();
+        assert_eq!(
+            vec![
+                dt(2024, 1, 1, 9, 0),
+                dt(2024, 1, 3, 9, 0),
+                dt(2024, 1, 4, 9, 0)
+            ],
+            starts
+        );
+    }
+}
@@ -0,0 +1,515 @@
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::PathBuf,
+    time::Duration,
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+use crate::list::{self, OutputFormat, SortKey};
+use crate::{
+    backend, duration, expand_schedules, load_between, parse_new_range, parse_range,
+    store_deletion, store_edit, store_new_schedule, time_zone, trim_all, when, Details, MyError,
+    OverlapPolicy, Schedule, ScheduleChanges, Status,
+};
+
+// これより大きいリクエストの本文は受け付けない
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+// リクエスト行とヘッダーを合わせた大きさの上限
+const MAX_HEAD_BYTES: u64 = 16 * 1024;
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// localhost だけで待ち受ける JSON の API
+///
+/// - GET /schedules?from=&to=&tz= 予定の一覧 (List --format json と同じ形)
+/// - POST /schedules 予定を追加する
+/// - PATCH /schedules/{id} 予定を変更する
+/// - DELETE /schedules/{id} 予定を削除する
+pub struct Server {
+    pub path: PathBuf,
+    pub zone: Tz,
+    // 相対的な日時の基準。None なら今
+    pub now: Option<DateTime<Utc>>,
+}
+
+impl Server {
+    /// 終了されるまで、1件ずつリクエストに応える
+    pub fn run(&self, listener: TcpListener) -> Result<(), MyError> {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|stream| self.handle(stream));
+            if let Err(error) = result {
+                eprintln!("エラー: {}", error);
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let response = match read_request(&mut BufReader::new(&stream)) {
+            Ok(request) => self
+                .route(&request)
+                .unwrap_or_else(|error| error_response(&error)),
+            Err(error) => Response::error(400, &error.to_string()),
+        };
+        response.write_to(&mut stream)
+    }
+
+    fn route(&self, request: &Request) -> Result<Response, MyError> {
+        let segments: Vec<_> = request.path.trim_matches('/').split('/').collect();
+        match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["schedules"]) => self.list(&request.query),
+            ("POST", ["schedules"]) => self.add(&request.body),
+            ("PATCH", ["schedules", id]) => self.edit(parse_id(id)?, &request.body),
+            ("DELETE", ["schedules", id]) => {
+                store_deletion(&self.path, parse_id(id)?, self.now())?;
+                Ok(Response {
+                    status: 204,
+                    body: String::new(),
+                })
+            }
+            (_, ["schedules"] | ["schedules", _]) => Ok(Response::error(
+                405,
+                &format!("{} には対応していません", request.method),
+            )),
+            _ => Ok(Response::error(
+                404,
+                &format!("{} はありません", request.path),
+            )),
+        }
+    }
+
+    fn list(&self, query: &[(String, String)]) -> Result<Response, MyError> {
+        let param = |key: &str| {
+            query
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value.as_str())
+        };
+        let zone = match param("tz") {
+            Some(tz) => tz
+                .parse()
+                .map_err(|_| MyError::Usage(format!("不明なタイムゾーンです: {}", tz)))?,
+            None => self.zone,
+        };
+        let from = param("from")
+            .map(|text| self.parse_time(text, zone))
+            .transpose()?;
+        let to = param("to")
+            .map(|text| self.parse_time(text, zone))
+            .transpose()?;
+
+        let calendar = load_between(backend::open(&self.path).as_ref(), from, to)?;
+        let mut entries: Vec<_> = expand_schedules(&calendar, from, to, self.now())
+            .into_iter()
+            .map(|schedule| list::Entry {
+                calendar: None,
+                schedule,
+            })
+            .collect();
+        list::sort_entries(&mut entries, SortKey::Start);
+        Ok(Response {
+            status: 200,
+            body: list::render(&entries, OutputFormat::Json, zone),
+        })
+    }
+
+    fn add(&self, body: &[u8]) -> Result<Response, MyError> {
+        let request: NewSchedule = parse_body(body)?;
+        let zone = request.time_zone.unwrap_or(self.zone);
+        let duration = request
+            .duration
+            .as_deref()
+            .map(duration::parse_duration)
+            .transpose()
+            .map_err(MyError::Usage)?;
+        let (start, end, all_day) = parse_range(
+            &request.start,
+            request.end.as_deref(),
+            duration,
+            self.local_now(zone),
+        )?;
+        let new_schedule = Schedule {
+            id: 0,
+            subject: request.subject,
+            details: Details {
+                attendees: trim_all(&request.details.attendees),
+                tags: trim_all(&request.details.tags),
+                ..request.details
+            },
+            start,
+            end,
+            time_zone: zone,
+            recurrence: None,
+            uid: None,
+            reminders: request.reminders,
+            all_day,
+            status: request.status,
+        };
+        let policy = OverlapPolicy {
+            per_attendee: request.per_attendee,
+            allow_overlap: request.allow_overlap,
+        };
+
+        let id = store_new_schedule(&self.path, new_schedule, &[], &policy, self.now())?;
+        Ok(Response::json(201, &Created { id }))
+    }
+
+    fn edit(&self, id: u64, body: &[u8]) -> Result<Response, MyError> {
+        let request: EditSchedule = parse_body(body)?;
+        let local_now = self.local_now(request.time_zone.unwrap_or(self.zone));
+        let range = parse_new_range(request.start.as_deref(), request.end.as_deref(), local_now)?;
+        let changes = ScheduleChanges {
+            subject: request.subject,
+            start: range.start,
+            end: range.end,
+            end_time: range.end_time,
+            all_day: range.all_day,
+            shift: request
+                .shift
+                .as_deref()
+                .map(duration::parse_signed_duration)
+                .transpose()
+                .map_err(MyError::Usage)?,
+            time_zone: request.time_zone,
+            reminders: request
+                .reminders
+                .map(|reminders| {
+                    reminders
+                        .iter()
+                        .map(|text| duration::parse_duration(text))
+                        .collect::<Result<Vec<TimeDelta>, _>>()
+                })
+                .transpose()
+                .map_err(MyError::Usage)?,
+            location: request.location,
+            description: request.description,
+            attendees: request.attendees.as_deref().map(trim_all),
+            tags: request.tags.as_deref().map(trim_all),
+            status: request.status,
+        };
+        let policy = OverlapPolicy {
+            per_attendee: request.per_attendee,
+            allow_overlap: request.allow_overlap,
+        };
+
+        store_edit(&self.path, id, &changes, &[], &policy, self.now())?;
+        Ok(Response::json(200, &Created { id }))
+    }
+
+    fn now(&self) -> DateTime<Utc> {
+        self.now.unwrap_or_else(Utc::now)
+    }
+
+    fn local_now(&self, zone: Tz) -> chrono::NaiveDateTime {
+        time_zone::to_local(zone, self.now())
+    }
+
+    // RFC 3339 の日時か、Add と同じ書き方の zone での日時
+    fn parse_time(&self, text: &str, zone: Tz) -> Result<DateTime<Utc>, MyError> {
+        if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+            return Ok(time.with_timezone(&Utc));
+        }
+        let when = when::parse_when(text, self.local_now(zone)).map_err(MyError::Usage)?;
+        Ok(time_zone::to_utc(zone, when.start()))
+    }
+}
+
+/// POST /schedules の本文。start と end は Add と同じ書き方
+#[derive(Deserialize)]
+struct NewSchedule {
+    subject: String,
+    start: String,
+    end: Option<String>,
+    // 例: 45m
+    duration: Option<String>,
+    time_zone: Option<Tz>,
+    #[serde(flatten)]
+    details: Details,
+    #[serde(default)]
+    status: Status,
+    #[serde(default, with = "duration::text_list")]
+    reminders: Vec<TimeDelta>,
+    #[serde(default)]
+    allow_overlap: bool,
+    #[serde(default)]
+    per_attendee: bool,
+}
+
+/// PATCH /schedules/{id} の本文。書かなかった項目は変えない
+#[derive(Deserialize)]
+struct EditSchedule {
+    subject: Option<String>,
+    start: Option<String>,
+    end: Option<String>,
+    // 例: -1h, 1d
+    shift: Option<String>,
+    time_zone: Option<Tz>,
+    reminders: Option<Vec<String>>,
+    // 空文字列なら消す
+    location: Option<String>,
+    description: Option<String>,
+    attendees: Option<Vec<String>>,
+    tags: Option<Vec<String>>,
+    status: Option<Status>,
+    #[serde(default)]
+    allow_overlap: bool,
+    #[serde(default)]
+    per_attendee: bool,
+}
+
+#[derive(Serialize)]
+struct Created {
+    id: u64,
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+    // 重なっている予定の ID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    conflicts: Option<&'a [u64]>,
+}
+
+fn parse_body<'a, T: Deserialize<'a>>(body: &'a [u8]) -> Result<T, MyError> {
+    serde_json::from_slice(body)
+        .map_err(|error| MyError::Usage(format!("本文の JSON を解釈できません: {}", error)))
+}
+
+fn parse_id(text: &str) -> Result<u64, MyError> {
+    text.parse()
+        .map_err(|_| MyError::Usage(format!("ID は数値で指定してください: {}", text)))
+}
+
+// 終了コードと同じく、エラーの種類ごとにステータスを決める
+fn error_response(error: &MyError) -> Response {
+    let (status, conflicts) = match error {
+        MyError::Usage(_) | MyError::InvalidRange { .. } => (400, None),
+        MyError::NotFound(_) | MyError::CalendarNotFound(_) => (404, None),
+        MyError::Conflict(ids) => (409, Some(ids.as_slice())),
+        // 今の状態と合わない操作なので、サーバーの不具合ではなく競合として返す
+        MyError::Stale(_) | MyError::CalendarExists(_) => (409, None),
+        _ => (500, None),
+    };
+    Response::json(
+        status,
+        &ErrorBody {
+            error: &error.to_string(),
+            conflicts,
+        },
+    )
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Request> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+    // 改行の来ない長い行で、いくらでも読み込まされないようにする
+    let mut remaining = MAX_HEAD_BYTES;
+    let mut read_line = |reader: &mut R| -> io::Result<String> {
+        let mut line = String::new();
+        let read = reader.by_ref().take(remaining).read_line(&mut line)?;
+        remaining -= read as u64;
+        if remaining == 0 && !line.ends_with('\n') {
+            return Err(invalid("ヘッダーが大きすぎます"));
+        }
+        Ok(line)
+    };
+
+    let request_line = read_line(reader)?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(invalid("リクエストを解釈できません"));
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let mut content_length = 0;
+    loop {
+        let line = read_line(reader)?;
+        if line.is_empty() {
+            break;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid("Content-Length を解釈できません"))?;
+            }
+        }
+    }
+    if content_length > MAX_BODY_BYTES {
+        return Err(invalid("本文が大きすぎます"));
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    Ok(Request {
+        method: method.to_string(),
+        path: percent_decode(path),
+        query: query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (percent_decode(key), percent_decode(value))
+            })
+            .collect(),
+        body,
+    })
+}
+
+// %XX だけを戻す。+ は空白にせず、+09:00 のようなオフセットとしてそのまま使う
+pub fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+struct Response {
+    status: u16,
+    body: String,
+}
+
+impl Response {
+    fn json(status: u16, value: &impl Serialize) -> Self {
+        Self {
+            status,
+            body: serde_json::to_string(value).unwrap() + "\n",
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self::json(
+            status,
+            &ErrorBody {
+                error: message,
+                conflicts: None,
+            },
+        )
+    }
+
+    fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        let reason = match self.status {
+            200 => "OK",
+            201 => "Created",
+            204 => "No Content",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            409 => "Conflict",
+            _ => "Internal Server Error",
+        };
+        write!(writer, "HTTP/1.1 {} {}\r\n", self.status, reason)?;
+        if !self.body.is_empty() {
+            write!(writer, "Content-Type: application/json; charset=utf-8\r\n")?;
+        }
+        write!(
+            writer,
+            "Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.body.len(),
+            self.body
+        )?;
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("2024-01-01T10%3A00%2B09%3A00", "2024-01-01T10:00+09:00")]
+    #[case("+09:00", "+09:00")]
+    #[case("%E4%BC%9A%E8%AD%B0", "会議")]
+    #[case("100%", "100%")]
+    fn test_percent_decode(#[case] text: &str, #[case] expected: &str) {
+        assert_eq!(expected, percent_decode(text));
+    }
+
+    #[test]
+    fn test_read_request() {
+        let text = "PATCH /schedules/3?from=2024-01-01&tz=Asia%2FTokyo HTTP/1.1\r\n\
+                    Host: localhost\r\n\
+                    content-length: 16\r\n\
+                    \r\n\
+                    {\"subject\":\"a\"}\n";
+        let request = read_request(&mut text.as_bytes()).unwrap();
+        assert_eq!("PATCH", request.method);
+        assert_eq!("/schedules/3", request.path);
+        assert_eq!(
+            vec![
+                ("from".to_string(), "2024-01-01".to_string()),
+                ("tz".to_string(), "Asia/Tokyo".to_string()),
+            ],
+            request.query
+        );
+        assert_eq!(b"{\"subject\":\"a\"}\n", request.body.as_slice());
+    }
+
+    #[test]
+    fn test_read_request_rejects_long_head() {
+        let long_line = format!(
+            "GET /{} HTTP/1.1\r\n\r\n",
+            "a".repeat(MAX_HEAD_BYTES as usize)
+        );
+        let many_headers = format!("GET / HTTP/1.1\r\n{}\r\n", "X-A: b\r\n".repeat(4096));
+        for text in [long_line, many_headers] {
+            let error = read_request(&mut text.as_bytes()).err().unwrap();
+            assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        }
+    }
+
+    #[rstest]
+    #[case(MyError::Conflict(vec![1, 2]), 409, Some(vec![1, 2]))]
+    #[case(MyError::Stale(3), 409, None)]
+    #[case(MyError::CalendarExists("work".to_string()), 409, None)]
+    #[case(MyError::NotFound(3), 404, None)]
+    #[case(MyError::Usage("x".to_string()), 400, None)]
+    #[case(MyError::Io(io::Error::other("x")), 500, None)]
+    fn test_error_response(
+        #[case] error: MyError,
+        #[case] status: u16,
+        #[case] conflicts: Option<Vec<u64>>,
+    ) {
+        let response = error_response(&error);
+        let body: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+        assert_eq!(status, response.status);
+        assert_eq!(error.to_string(), body["error"]);
+        assert_eq!(
+            conflicts.map_or(serde_json::Value::Null, |ids| serde_json::json!(ids)),
+            body["conflicts"]
+        );
+    }
+}
@@ -0,0 +1,492 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Datelike, NaiveDate, NaiveTime, TimeDelta, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+
+use crate::free::{self, FreeQuery};
+use crate::list::OutputFormat;
+use crate::{expand_between, time_zone, Calendar};
+
+/// 予定の長さをまとめる単位
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum GroupBy {
+    #[default]
+    Day,
+    /// 月曜始まりの週
+    Week,
+    /// 件名 (--keyword を指定するとキーワードごと)
+    Subject,
+    Tag,
+}
+
+/// 集計の条件
+pub struct StatsQuery {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub by: GroupBy,
+    // 件名に含まれるキーワード (大文字と小文字は区別しない)
+    pub keywords: Vec<String>,
+    pub work_start: NaiveTime,
+    pub work_end: NaiveTime,
+    pub time_zone: Tz,
+    // 忙しい日として表示する日数
+    pub top: usize,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Report {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub groups: Vec<Group>,
+    // 勤務時間のうち予定の入っている時間。空き時間と同じく終日の予定も含め、重なりは一度だけ数える
+    pub booked_minutes: i64,
+    pub work_minutes: i64,
+    pub free_minutes: i64,
+    pub busiest_days: Vec<BusyDay>,
+}
+
+/// まとめた単位ごとの予定の数と長さの合計
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Group {
+    pub key: String,
+    pub count: usize,
+    pub minutes: i64,
+}
+
+struct WorkMinutes {
+    total: i64,
+    booked: i64,
+    free: i64,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct BusyDay {
+    pub date: NaiveDate,
+    pub minutes: i64,
+}
+
+const OTHERS: &str = "(その他)";
+const UNTAGGED: &str = "(タグなし)";
+
+/// from から to までの予定の長さを集計する
+///
+/// 空きとして扱う予定や仮の予定、終日の予定は時間を数えない。
+/// ただし勤務時間中の予定の時間は、空き時間と同じく終日の予定も含めて勤務時間の中だけを数える
+pub fn report(calendar: &Calendar, query: &StatsQuery) -> Report {
+    let zone = query.time_zone;
+    let from = time_zone::to_utc(zone, query.from.and_time(NaiveTime::MIN));
+    let to = time_zone::to_utc(
+        zone,
+        (query.to + TimeDelta::days(1)).and_time(NaiveTime::MIN),
+    );
+
+    let mut groups: BTreeMap<String, (usize, i64)> = BTreeMap::new();
+    let mut booked = Vec::new();
+    for schedule in expand_between(calendar, from, to) {
+        if schedule.all_day || !schedule.status.is_busy() {
+            continue;
+        }
+        let start = schedule.start_utc().max(from);
+        let end = schedule.end_utc().min(to);
+        if end <= start {
+            continue;
+        }
+        booked.push((start, end));
+
+        let mut add = |key: String, minutes: i64| {
+            let group = groups.entry(key).or_default();
+            group.0 += 1;
+            group.1 += minutes;
+        };
+        match query.by {
+            GroupBy::Day | GroupBy::Week => {
+                for (date, minutes) in split_by_day(start, end, zone) {
+                    let key = match query.by {
+                        GroupBy::Week => monday_of(date),
+                        _ => date,
+                    };
+                    add(key.to_string(), minutes);
+                }
+            }
+            GroupBy::Subject => {
+                let minutes = (end - start).num_minutes();
+                for key in subject_keys(&schedule.subject, &query.keywords) {
+                    add(key, minutes);
+                }
+            }
+            GroupBy::Tag => {
+                let minutes = (end - start).num_minutes();
+                let tags = &schedule.details.tags;
+                if tags.is_empty() {
+                    add(UNTAGGED.to_string(), minutes);
+                }
+                for tag in tags {
+                    add(tag.clone(), minutes);
+                }
+            }
+        }
+    }
+
+    let booked = merge(booked);
+    let mut days: BTreeMap<NaiveDate, i64> = BTreeMap::new();
+    for (start, end) in &booked {
+        for (date, minutes) in split_by_day(*start, *end, zone) {
+            *days.entry(date).or_default() += minutes;
+        }
+    }
+    let mut busiest_days: Vec<_> = days
+        .into_iter()
+        .map(|(date, minutes)| BusyDay { date, minutes })
+        .collect();
+    // 同じ長さなら日付の早い順
+    busiest_days.sort_by_key(|day| (-day.minutes, day.date));
+    busiest_days.truncate(query.top);
+
+    let work = work_minutes(calendar, query);
+    Report {
+        from: query.from,
+        to: query.to,
+        groups: groups
+            .into_iter()
+            .map(|(key, (count, minutes))| Group {
+                key,
+                count,
+                minutes,
+            })
+            .collect(),
+        booked_minutes: work.booked,
+        work_minutes: work.total,
+        free_minutes: work.free,
+        busiest_days,
+    }
+}
+
+// 件名が含むキーワード。キーワードを指定しなければ件名そのもの
+fn subject_keys(subject: &str, keywords: &[String]) -> Vec<String> {
+    if keywords.is_empty() {
+        return vec![subject.to_string()];
+    }
+    let subject = subject.to_lowercase();
+    let keys: Vec<_> = keywords
+        .iter()
+        .filter(|keyword| subject.contains(&keyword.to_lowercase()))
+        .cloned()
+        .collect();
+    if keys.is_empty() {
+        vec![OTHERS.to_string()]
+    } else {
+        keys
+    }
+}
+
+/// date を含む週の月曜日
+pub fn monday_of(date: NaiveDate) -> NaiveDate {
+    date - TimeDelta::days(date.weekday().num_days_from_monday() as i64)
+}
+
+// [start, end) を zone での日付ごとに区切り、各日の分数を返す
+fn split_by_day(start: DateTime<Utc>, end: DateTime<Utc>, zone: Tz) -> Vec<(NaiveDate, i64)> {
+    let mut days = Vec::new();
+    let mut cursor = start;
+    while cursor < end {
+        let date = time_zone::to_local(zone, cursor).date();
+        let midnight =
+            time_zone::to_utc(zone, (date + TimeDelta::days(1)).and_time(NaiveTime::MIN));
+        let day_end = end.min(midnight);
+        days.push((date, (day_end - cursor).num_minutes()));
+        cursor = day_end;
+    }
+    days
+}
+
+// 重なっている区間をつなげる
+fn merge(
+    mut intervals: Vec<(DateTime<Utc>, DateTime<Utc>)>,
+) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    intervals.sort();
+    let mut merged: Vec<(DateTime<Utc>, DateTime<Utc>)> = Vec::new();
+    for (start, end) in intervals {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+// 勤務時間の合計と、そのうち予定の入っている時間と入っていない時間 (Free と同じ数え方)。
+// 二つの合計は勤務時間の合計と一致する
+fn work_minutes(calendar: &Calendar, query: &StatsQuery) -> WorkMinutes {
+    let free_query = FreeQuery {
+        from: query.from,
+        to: query.to,
+        work_start: query.work_start,
+        work_end: query.work_end,
+        min_duration: TimeDelta::zero(),
+        time_zone: query.time_zone,
+        now: DateTime::<Utc>::MIN_UTC,
+    };
+    let windows = free::work_windows(&free_query);
+    let busy = merge(free::busy_intervals(calendar, &windows));
+    let booked = windows
+        .iter()
+        .map(|(window_start, window_end)| {
+            busy.iter()
+                .map(|(start, end)| {
+                    let start = (*start).max(*window_start);
+                    let end = (*end).min(*window_end);
+                    (end - start).num_minutes().max(0)
+                })
+                .sum::<i64>()
+        })
+        .sum();
+    WorkMinutes {
+        total: windows
+            .iter()
+            .map(|(start, end)| (*end - *start).num_minutes())
+            .sum(),
+        booked,
+        free: free::find_free_slots(calendar, &free_query)
+            .iter()
+            .map(|slot| slot.minutes)
+            .sum(),
+    }
+}
+
+/// 集計結果を指定の形式の文字列にする。CSV はまとめた単位の表だけを出す
+pub fn render(report: &Report, format: OutputFormat) -> String {
+    let header = ["KEY", "COUNT", "MINUTES", "HOURS"];
+    let rows: Vec<[String; 4]> = report
+        .groups
+        .iter()
+        .map(|group| {
+            [
+                group.key.clone(),
+                group.count.to_string(),
+                group.minutes.to_string(),
+                hours(group.minutes),
+            ]
+        })
+        .collect();
+
+    let mut text = match format {
+        OutputFormat::Json => return serde_json::to_string_pretty(report).unwrap() + "\n",
+        OutputFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(vec![]);
+            writer.write_record(header).unwrap();
+            for row in rows {
+                writer.write_record(&row).unwrap();
+            }
+            return String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        }
+        OutputFormat::Table => {
+            let mut text = header.join("\t") + "\n";
+            for row in rows {
+                text += &(row.join("\t") + "\n");
+            }
+            text
+        }
+        OutputFormat::Markdown => {
+            let mut text = format!("| {} |\n", header.join(" | "));
+            text += &format!("|{}\n", " --- |".repeat(header.len()));
+            for row in rows {
+                let cells: Vec<_> = row.iter().map(|cell| cell.replace('|', "\\|")).collect();
+                text += &format!("| {} |\n", cells.join(" | "));
+            }
+            text
+        }
+    };
+
+    text += &format!("\n勤務時間中の予定: {}時間\n", hours(report.booked_minutes));
+    text += &format!(
+        "勤務時間中の空き時間: {}時間 (勤務時間 {}時間)\n",
+        hours(report.free_minutes),
+        hours(report.work_minutes)
+    );
+    if !report.busiest_days.is_empty() {
+        let days: Vec<_> = report
+            .busiest_days
+            .iter()
+            .map(|day| format!("{} ({}時間)", day.date, hours(day.minutes)))
+            .collect();
+        text += &format!("忙しい日: {}\n", days.join(", "));
+    }
+    text
+}
+
+fn hours(minutes: i64) -> String {
+    format!("{:.1}", minutes as f64 / 60.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recurrence::{Frequency, Recurrence};
+    use crate::{Schedule, Status};
+    use chrono::NaiveDateTime;
+    use rstest::rstest;
+
+    fn dt(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    // 2024年1月1日 (月) からの1週間
+    fn calendar() -> Calendar {
+        let mut review = Schedule::test(2, "Code Review", dt(2, 13, 0), dt(2, 14, 30));
+        review.details.tags = vec!["work".to_string()];
+        let mut planning = Schedule::test(3, "計画会議", dt(2, 14, 0), dt(2, 15, 0));
+        planning.details.tags = vec!["work".to_string(), "meeting".to_string()];
+        Calendar {
+            schedules: vec![
+                Schedule {
+                    recurrence: Some(Recurrence {
+                        count: Some(3),
+                        ..Recurrence::new(Frequency::Daily)
+                    }),
+                    ..Schedule::test(0, "朝会", dt(1, 9, 0), dt(1, 9, 30))
+                },
+                // 日付をまたぐ予定は日ごとに分ける
+                Schedule::test(1, "夜間作業", dt(3, 23, 0), dt(4, 1, 0)),
+                review,
+                planning,
+                Schedule {
+                    status: Status::Tentative,
+                    ..Schedule::test(4, "仮の会議", dt(5, 10, 0), dt(5, 11, 0))
+                },
+                Schedule {
+                    all_day: true,
+                    ..Schedule::test(5, "休暇", dt(5, 0, 0), dt(6, 0, 0))
+                },
+            ],
+            next_id: 6,
+        }
+    }
+
+    fn query(by: GroupBy, keywords: &[&str]) -> StatsQuery {
+        StatsQuery {
+            from: dt(1, 0, 0).date(),
+            to: dt(7, 0, 0).date(),
+            by,
+            keywords: keywords.iter().map(|keyword| keyword.to_string()).collect(),
+            work_start: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+            work_end: NaiveTime::from_hms_opt(18, 0, 0).unwrap(),
+            time_zone: Tz::UTC,
+            top: 2,
+        }
+    }
+
+    #[rstest]
+    #[case(GroupBy::Day, &[], vec![
+        ("2024-01-01", 1, 30),
+        ("2024-01-02", 3, 180),
+        ("2024-01-03", 2, 90),
+        ("2024-01-04", 1, 60),
+    ])]
+    #[case(GroupBy::Week, &[], vec![("2024-01-01", 7, 360)])]
+    #[case(GroupBy::Subject, &[], vec![
+        ("Code Review", 1, 90),
+        ("夜間作業", 1, 120),
+        ("朝会", 3, 90),
+        ("計画会議", 1, 60),
+    ])]
+    #[case(GroupBy::Subject, &["review", "会"], vec![
+        ("(その他)", 1, 120),
+        ("review", 1, 90),
+        ("会", 4, 150),
+    ])]
+    #[case(GroupBy::Tag, &[], vec![
+        ("(タグなし)", 4, 210),
+        ("meeting", 1, 60),
+        ("work", 2, 150),
+    ])]
+    fn test_report_groups(
+        #[case] by: GroupBy,
+        #[case] keywords: &[&str],
+        #[case] expected: Vec<(&str, usize, i64)>,
+    ) {
+        let report = report(&calendar(), &query(by, keywords));
+        let groups: Vec<_> = report
+            .groups
+            .iter()
+            .map(|group| (group.key.as_str(), group.count, group.minutes))
+            .collect();
+        assert_eq!(expected, groups);
+    }
+
+    #[test]
+    fn test_report_summary() {
+        let report = report(&calendar(), &query(GroupBy::Day, &[]));
+
+        // 重なっている 14:00-14:30 は一度だけ数え、勤務時間外の夜間作業は数えず終日の予定は数える
+        assert_eq!(30 * 3 + 120 + 9 * 60, report.booked_minutes);
+        assert_eq!(7 * 9 * 60, report.work_minutes);
+        // 勤務時間外の夜間作業と、仮の予定は空き時間を減らさない。終日の予定は減らす
+        assert_eq!(7 * 9 * 60 - 30 * 3 - 120 - 9 * 60, report.free_minutes);
+        assert_eq!(
+            vec![
+                BusyDay {
+                    date: dt(2, 0, 0).date(),
+                    minutes: 150
+                },
+                BusyDay {
+                    date: dt(3, 0, 0).date(),
+                    minutes: 90
+                },
+            ],
+            report.busiest_days
+        );
+    }
+
+    #[rstest]
+    #[case(Tz::UTC)]
+    #[case(Tz::Asia__Tokyo)]
+    fn test_report_booked_and_free_add_up(#[case] time_zone: Tz) {
+        let mut calendar = calendar();
+        calendar.schedules.extend([
+            // 勤務時間の始まりと終わりにかかる予定
+            Schedule::test(6, "早朝作業", dt(6, 8, 0), dt(6, 10, 0)),
+            Schedule::test(7, "残業", dt(6, 17, 0), dt(6, 19, 30)),
+            Schedule {
+                status: Status::Free,
+                ..Schedule::test(8, "自習", dt(7, 9, 0), dt(7, 12, 0))
+            },
+        ]);
+        let query = StatsQuery {
+            time_zone,
+            ..query(GroupBy::Day, &[])
+        };
+
+        let report = report(&calendar, &query);
+        assert_eq!(
+            report.work_minutes,
+            report.booked_minutes + report.free_minutes
+        );
+        assert!(report.booked_minutes > 0);
+    }
+
+    #[test]
+    fn test_render() {
+        let report = report(&calendar(), &query(GroupBy::Week, &[]));
+        assert_eq!(
+            "KEY\tCOUNT\tMINUTES\tHOURS\n\
+             2024-01-01\t7\t360\t6.0\n\
+             \n\
+             勤務時間中の予定: 12.5時間\n\
+             勤務時間中の空き時間: 50.5時間 (勤務時間 63.0時間)\n\
+             忙しい日: 2024-01-02 (2.5時間), 2024-01-03 (1.5時間)\n",
+            render(&report, OutputFormat::Table)
+        );
+        assert_eq!(
+            "KEY,COUNT,MINUTES,HOURS\n2024-01-01,7,360,6.0\n",
+            render(&report, OutputFormat::Csv)
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&render(&report, OutputFormat::Json)).unwrap();
+        assert_eq!(750, json["booked_minutes"]);
+        assert_eq!("2024-01-02", json["busiest_days"][0]["date"]);
+    }
+}
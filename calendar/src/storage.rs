@@ -0,0 +1,134 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+/// 保存のたびに残す以前のファイルの数 (schedule.json.1 が一番新しい)
+pub const BACKUP_COUNT: usize = 5;
+
+/// カレンダーファイルの排他ロック。drop すると解放される
+///
+/// OS の advisory lock なので、ロックを取らずに書き込む処理は防げない
+pub struct FileLock {
+    _file: File,
+}
+
+impl FileLock {
+    /// path 用のロックファイルを作ってロックする。他のプロセスが持っている間は待つ
+    pub fn acquire(path: &Path) -> io::Result<Self> {
+        create_parent_dir(path)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(with_suffix(path, ".lock"))?;
+        file.lock()?;
+        Ok(Self { _file: file })
+    }
+}
+
+/// 同じディレクトリの一時ファイルに書いてから rename で置き換える
+///
+/// 書き込みの途中で落ちても、元のファイルは壊れずに残る
+pub fn write_atomic(
+    path: &Path,
+    write: impl FnOnce(&mut File) -> io::Result<()>,
+) -> io::Result<()> {
+    create_parent_dir(path)?;
+    let temp_path = temp_path(path);
+    let result = File::create(&temp_path).and_then(|mut file| {
+        write(&mut file)?;
+        file.sync_all()
+    });
+    if let Err(error) = result.and_then(|()| fs::rename(&temp_path, path)) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(error);
+    }
+    Ok(())
+}
+
+/// 今のファイルを path.1 にコピーし、それまでのバックアップを一つずつずらす
+pub fn rotate_backups(path: &Path, count: usize) -> io::Result<()> {
+    if count == 0 || !path.exists() {
+        return Ok(());
+    }
+    for n in (1..count).rev() {
+        match fs::rename(backup_path(path, n), backup_path(path, n + 1)) {
+            Err(error) if error.kind() != ErrorKind::NotFound => return Err(error),
+            _ => {}
+        }
+    }
+    fs::copy(path, backup_path(path, 1))?;
+    Ok(())
+}
+
+pub fn backup_path(path: &Path, n: usize) -> PathBuf {
+    with_suffix(path, &format!(".{}", n))
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    // . で始まる名前はカレンダーとして扱われない
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(".{}.tmp", std::process::id()));
+    path.with_file_name(name)
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_os_string();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+pub fn create_parent_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => fs::create_dir_all(parent),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir;
+    use std::io::Write;
+
+    #[test]
+    fn test_write_atomic_with_backups() {
+        let dir = test_dir("storage");
+        let path = dir.join("schedule.json");
+
+        for n in 0..=BACKUP_COUNT + 1 {
+            rotate_backups(&path, BACKUP_COUNT).unwrap();
+            write_atomic(&path, |file| write!(file, "{}", n)).unwrap();
+        }
+        let failed = write_atomic(&path, |_| Err(io::Error::other("失敗")));
+
+        let mut names: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        let latest = fs::read_to_string(&path).unwrap();
+        let oldest = fs::read_to_string(backup_path(&path, BACKUP_COUNT)).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(failed.is_err());
+        // 失敗しても元のファイルが残り、一時ファイルは消える
+        assert_eq!("6", latest);
+        assert_eq!("1", oldest);
+        assert_eq!(
+            vec![
+                "schedule.json",
+                "schedule.json.1",
+                "schedule.json.2",
+                "schedule.json.3",
+                "schedule.json.4",
+                "schedule.json.5",
+            ],
+            names
+        );
+    }
+}
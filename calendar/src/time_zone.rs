@@ -0,0 +1,68 @@
+use chrono::{DateTime, LocalResult, NaiveDateTime, Offset, TimeDelta, TimeZone, Utc};
+use chrono_tz::Tz;
+
+/// 手元のタイムゾーン。TZ 環境変数、OS の設定の順に調べ、分からなければ UTC
+pub fn local_time_zone() -> Tz {
+    std::env::var("TZ")
+        .ok()
+        .and_then(|name| name.parse().ok())
+        .or_else(|| {
+            iana_time_zone::get_timezone()
+                .ok()
+                .and_then(|name| name.parse().ok())
+        })
+        .unwrap_or(Tz::UTC)
+}
+
+/// タイムゾーン上の壁時計の日時を UTC の時刻に直す
+///
+/// 夏時間の切り替えで重複する時刻は早い方、存在しない時刻は切り替え前の
+/// オフセットで解釈する (RFC 5545 と同じ)
+pub fn to_utc(time_zone: Tz, local: NaiveDateTime) -> DateTime<Utc> {
+    match time_zone.from_local_datetime(&local) {
+        LocalResult::Single(time) | LocalResult::Ambiguous(time, _) => time.with_timezone(&Utc),
+        LocalResult::None => {
+            let before = local - TimeDelta::hours(1);
+            let offset = time_zone
+                .offset_from_local_datetime(&before)
+                .earliest()
+                .map_or(0, |offset| offset.fix().local_minus_utc());
+            Utc.from_utc_datetime(&(local - TimeDelta::seconds(offset as i64)))
+        }
+    }
+}
+
+/// UTC の時刻をタイムゾーン上の壁時計の日時に直す
+pub fn to_local(time_zone: Tz, time: DateTime<Utc>) -> NaiveDateTime {
+    time.with_timezone(&time_zone).naive_local()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use rstest::rstest;
+
+    fn dt(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(year, month, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[rstest]
+    #[case(Tz::Asia__Tokyo, dt(2024, 1, 1, 9, 0), dt(2024, 1, 1, 0, 0))]
+    #[case(Tz::Europe__Berlin, dt(2024, 1, 1, 9, 0), dt(2024, 1, 1, 8, 0))]
+    #[case(Tz::Europe__Berlin, dt(2024, 7, 1, 9, 0), dt(2024, 7, 1, 7, 0))]
+    // 夏時間開始で存在しない 2:30 は切り替え前の +01:00 で解釈する
+    #[case(Tz::Europe__Berlin, dt(2024, 3, 31, 2, 30), dt(2024, 3, 31, 1, 30))]
+    // 夏時間終了で2回ある 2:30 は早い方 (+02:00)
+    #[case(Tz::Europe__Berlin, dt(2024, 10, 27, 2, 30), dt(2024, 10, 27, 0, 30))]
+    fn test_to_utc(
+        #[case] time_zone: Tz,
+        #[case] local: NaiveDateTime,
+        #[case] expected: NaiveDateTime,
+    ) {
+        assert_eq!(expected, to_utc(time_zone, local).naive_utc());
+    }
+}
@@ -0,0 +1,446 @@
+use chrono::{Datelike, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, Timelike};
+use chrono_tz::Tz;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::{time_zone, Schedule};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Span {
+    #[default]
+    Month,
+    Week,
+    Day,
+}
+
+// 月の表の1日の幅と、表示する予定の数
+const MONTH_CELL_WIDTH: usize = 14;
+const MONTH_CELL_ITEMS: usize = 3;
+// 週と日の表の列の幅
+const WEEK_CELL_WIDTH: usize = 14;
+const DAY_CELL_WIDTH: usize = 48;
+// 予定が無ければ 8時から 20時までを表示する
+const DEFAULT_FIRST_HOUR: u32 = 8;
+const DEFAULT_LAST_HOUR: u32 = 20;
+
+const WEEKDAYS: [&str; 7] = ["月", "火", "水", "木", "金", "土", "日"];
+
+/// 表に置く予定。日時は表示するタイムゾーンでの日時
+struct Item {
+    subject: String,
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+    all_day: bool,
+    // 他の予定と重なっている
+    overlap: bool,
+}
+
+impl Item {
+    fn on(&self, date: NaiveDate) -> bool {
+        let day_start = date.and_time(NaiveTime::MIN);
+        self.start < day_start + TimeDelta::days(1) && day_start < self.end
+    }
+
+    fn marker(&self) -> &'static str {
+        if self.overlap {
+            "!"
+        } else {
+            " "
+        }
+    }
+}
+
+/// 表を描く条件
+pub struct Grid {
+    pub span: Span,
+    /// この日を含む月・週・日を表示する
+    pub date: NaiveDate,
+    pub today: NaiveDate,
+    pub zone: Tz,
+    /// 今日を反転表示する (端末に出力するとき)
+    pub color: bool,
+}
+
+impl Grid {
+    /// 表示する期間の [最初の日, 最後の日]
+    pub fn range(&self) -> (NaiveDate, NaiveDate) {
+        match self.span {
+            Span::Month => {
+                let first = self.date.with_day(1).unwrap();
+                let next = first + chrono::Months::new(1);
+                (
+                    monday_of(first),
+                    monday_of(next - TimeDelta::days(1)) + TimeDelta::days(6),
+                )
+            }
+            Span::Week => {
+                let monday = monday_of(self.date);
+                (monday, monday + TimeDelta::days(6))
+            }
+            Span::Day => (self.date, self.date),
+        }
+    }
+
+    /// 予定 (繰り返しは展開済み) を表にする
+    pub fn render(&self, schedules: &[Schedule]) -> String {
+        let items = self.items(schedules);
+        match self.span {
+            Span::Month => self.render_month(&items),
+            Span::Week => {
+                let (first, _) = self.range();
+                let days: Vec<_> = first.iter_days().take(7).collect();
+                self.render_timeline(&items, &days, WEEK_CELL_WIDTH, 60)
+            }
+            Span::Day => self.render_timeline(&items, &[self.date], DAY_CELL_WIDTH, 30),
+        }
+    }
+
+    fn items(&self, schedules: &[Schedule]) -> Vec<Item> {
+        let mut items: Vec<_> = schedules
+            .iter()
+            .map(|schedule| {
+                // 終日の予定はタイムゾーンを変換せず、その日付に置く
+                let (start, end) = if schedule.all_day {
+                    (schedule.start, schedule.end)
+                } else {
+                    (
+                        time_zone::to_local(self.zone, schedule.start_utc()),
+                        time_zone::to_local(self.zone, schedule.end_utc()),
+                    )
+                };
+                Item {
+                    subject: schedule.subject.clone(),
+                    start,
+                    end,
+                    all_day: schedule.all_day,
+                    overlap: false,
+                }
+            })
+            .collect();
+        items.sort_by_key(|item| (!item.all_day, item.start));
+
+        for i in 0..items.len() {
+            items[i].overlap = (0..items.len())
+                .any(|j| i != j && items[i].start < items[j].end && items[j].start < items[i].end);
+        }
+        items
+    }
+
+    fn highlight(&self, text: String, date: NaiveDate) -> String {
+        if date != self.today {
+            return text;
+        }
+        if self.color {
+            format!("\x1b[7m{}\x1b[0m", text)
+        } else {
+            text
+        }
+    }
+
+    fn render_month(&self, items: &[Item]) -> String {
+        let (first, last) = self.range();
+        let border = format!(
+            "+{}\n",
+            format!("{}+", "-".repeat(MONTH_CELL_WIDTH)).repeat(7)
+        );
+
+        let mut text = format!("{}年{}月\n", self.date.year(), self.date.month());
+        text += &border;
+        text += "|";
+        for weekday in WEEKDAYS {
+            text += &format!("{}|", fit(weekday, MONTH_CELL_WIDTH));
+        }
+        text += "\n";
+        text += &border;
+
+        let days: Vec<_> = first.iter_days().take_while(|day| *day <= last).collect();
+        for week in days.chunks(7) {
+            let mut lines = vec![String::from("|"); MONTH_CELL_ITEMS + 1];
+            for day in week {
+                // 今日は [18] のように括弧で囲む
+                let label = if *day == self.today {
+                    format!("[{}]", day.day())
+                } else if day.month() == self.date.month() {
+                    day.day().to_string()
+                } else {
+                    format!("{}/{}", day.month(), day.day())
+                };
+                lines[0] += &self.highlight(fit(&label, MONTH_CELL_WIDTH), *day);
+                lines[0] += "|";
+
+                let today_items: Vec<_> = items.iter().filter(|item| item.on(*day)).collect();
+                for (n, line) in lines[1..].iter_mut().enumerate() {
+                    let cell = match today_items.get(n) {
+                        // 入りきらない分は件数だけ表示する
+                        Some(_) if n + 1 == MONTH_CELL_ITEMS && today_items.len() > n + 1 => {
+                            format!(" +{}件", today_items.len() - n)
+                        }
+                        Some(item) => month_label(item, *day),
+                        None => String::new(),
+                    };
+                    *line += &fit(&cell, MONTH_CELL_WIDTH);
+                    *line += "|";
+                }
+            }
+            for line in lines {
+                text += &line;
+                text += "\n";
+            }
+            text += &border;
+        }
+        text
+    }
+
+    fn render_timeline(
+        &self,
+        items: &[Item],
+        days: &[NaiveDate],
+        width: usize,
+        slot_minutes: u32,
+    ) -> String {
+        const LABEL_WIDTH: usize = 6;
+        let border = format!(
+            "+{}+{}\n",
+            "-".repeat(LABEL_WIDTH),
+            format!("{}+", "-".repeat(width)).repeat(days.len())
+        );
+
+        let mut text = border.clone();
+        text += &format!("|{}|", fit("", LABEL_WIDTH));
+        for day in days {
+            let weekday = WEEKDAYS[day.weekday().num_days_from_monday() as usize];
+            let label = format!("{} {}/{}", weekday, day.month(), day.day());
+            let label = if *day == self.today {
+                format!("[{}]", label)
+            } else {
+                label
+            };
+            text += &self.highlight(fit(&label, width), *day);
+            text += "|";
+        }
+        text += "\n";
+        text += &border;
+
+        // 終日の予定と、日をまたぐ予定
+        let all_day: Vec<Vec<&Item>> = days
+            .iter()
+            .map(|day| {
+                items
+                    .iter()
+                    .filter(|item| {
+                        item.on(*day) && (item.all_day || item.start.date() != item.end.date())
+                    })
+                    .collect()
+            })
+            .collect();
+        let all_day_rows = all_day.iter().map(Vec::len).max().unwrap_or(0);
+        for n in 0..all_day_rows {
+            text += &format!("|{}|", fit(if n == 0 { "終日" } else { "" }, LABEL_WIDTH));
+            for (day, day_items) in days.iter().zip(&all_day) {
+                let cell = day_items
+                    .get(n)
+                    .map(|item| month_label(item, *day))
+                    .unwrap_or_default();
+                text += &fit(&cell, width);
+                text += "|";
+            }
+            text += "\n";
+        }
+        if all_day_rows > 0 {
+            text += &border;
+        }
+
+        let timed: Vec<_> = items
+            .iter()
+            .filter(|item| !item.all_day && days.iter().any(|day| item.on(*day)))
+            .collect();
+        let (first_hour, last_hour) = hour_range(&timed, days);
+        let slot = TimeDelta::minutes(slot_minutes as i64);
+        let mut time = NaiveTime::from_hms_opt(first_hour, 0, 0).unwrap();
+        let slots = (last_hour - first_hour) * 60 / slot_minutes;
+        for _ in 0..slots {
+            text += &format!("|{}|", fit(&time.format(" %H:%M").to_string(), LABEL_WIDTH));
+            for day in days {
+                let slot_start = day.and_time(time);
+                let slot_end = slot_start + slot;
+                let in_slot: Vec<_> = timed
+                    .iter()
+                    .filter(|item| item.start < slot_end && slot_start < item.end)
+                    .collect();
+                text += &fit(&slot_label(&in_slot, slot_start, slot_end), width);
+                text += "|";
+            }
+            text += "\n";
+            time += slot;
+        }
+        text += &border;
+        text
+    }
+}
+
+// 月の表と終日の行に書く予定の名前
+fn month_label(item: &Item, day: NaiveDate) -> String {
+    if item.all_day || item.start.date() < day {
+        // 前の日から続いている予定には → を付ける
+        let arrow = if item.start.date() < day { "→" } else { "" };
+        format!("{}{}{}", item.marker(), arrow, item.subject)
+    } else {
+        format!(
+            "{}{} {}",
+            item.marker(),
+            item.start.format("%H:%M"),
+            item.subject
+        )
+    }
+}
+
+// 時間の枠に書く内容。枠の中で始まる予定は名前、続いている予定は │ にする
+fn slot_label(items: &[&&Item], slot_start: NaiveDateTime, slot_end: NaiveDateTime) -> String {
+    let Some(first) = items.first() else {
+        return String::new();
+    };
+    let starts_here = |item: &Item| slot_start <= item.start && item.start < slot_end;
+    let shown = items.iter().find(|item| starts_here(item)).unwrap_or(first);
+    let name = if starts_here(shown) {
+        format!("{} {}", shown.start.format("%H:%M"), shown.subject)
+    } else {
+        "│".to_string()
+    };
+    if items.len() > 1 {
+        format!("!{} +{}", name, items.len() - 1)
+    } else {
+        format!("{}{}", shown.marker(), name)
+    }
+}
+
+// 表示する時間の範囲。予定が範囲の外にあれば広げる
+fn hour_range(items: &[&Item], days: &[NaiveDate]) -> (u32, u32) {
+    let mut first = DEFAULT_FIRST_HOUR;
+    let mut last = DEFAULT_LAST_HOUR;
+    for item in items {
+        for day in days.iter().filter(|day| item.on(**day)) {
+            let day_start = day.and_time(NaiveTime::MIN);
+            let start = item.start.max(day_start);
+            let end = item.end.min(day_start + TimeDelta::days(1));
+            first = first.min(start.hour());
+            let end_hour = if end.date() > *day {
+                24
+            } else {
+                end.hour() + u32::from(end.minute() > 0)
+            };
+            last = last.max(end_hour);
+        }
+    }
+    (first, last)
+}
+
+fn monday_of(date: NaiveDate) -> NaiveDate {
+    date - TimeDelta::days(date.weekday().num_days_from_monday() as i64)
+}
+
+/// 表示幅が width になるように、切り詰めるか空白で埋める (全角文字は幅2)
+fn fit(text: &str, width: usize) -> String {
+    if text.width() <= width {
+        return format!("{}{}", text, " ".repeat(width - text.width()));
+    }
+    let mut fitted = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        let char_width = c.width().unwrap_or(0);
+        if used + char_width + 1 > width {
+            break;
+        }
+        fitted.push(c);
+        used += char_width;
+    }
+    fitted.push('…');
+    used += 1;
+    fitted + &" ".repeat(width - used)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn dt(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    fn grid(span: Span) -> Grid {
+        Grid {
+            span,
+            date: dt(10, 0, 0).date(),
+            today: dt(10, 0, 0).date(),
+            zone: Tz::UTC,
+            color: false,
+        }
+    }
+
+    #[rstest]
+    #[case("abc", 5, "abc  ")]
+    #[case("定例会議", 8, "定例会議")]
+    #[case("定例会議", 7, "定例会…")]
+    #[case("定例会議", 6, "定例… ")]
+    fn test_fit(#[case] text: &str, #[case] width: usize, #[case] expected: &str) {
+        assert_eq!(expected, fit(text, width));
+        assert_eq!(width, fit(text, width).width());
+    }
+
+    #[rstest]
+    // 2024年1月は月曜日に始まり、2月4日 (日) までの5週
+    #[case(Span::Month, (1, 1), (2, 4))]
+    #[case(Span::Week, (1, 8), (1, 14))]
+    #[case(Span::Day, (1, 10), (1, 10))]
+    fn test_range(#[case] span: Span, #[case] first: (u32, u32), #[case] last: (u32, u32)) {
+        let date = |(month, day)| NaiveDate::from_ymd_opt(2024, month, day).unwrap();
+        assert_eq!((date(first), date(last)), grid(span).range());
+    }
+
+    #[test]
+    fn test_render_month() {
+        let holiday = Schedule {
+            all_day: true,
+            ..Schedule::test(0, "休暇", dt(11, 0, 0), dt(13, 0, 0))
+        };
+        let schedules = vec![
+            Schedule::test(0, "定例", dt(10, 10, 0), dt(10, 11, 0)),
+            Schedule::test(0, "面談", dt(10, 10, 30), dt(10, 11, 30)),
+            holiday,
+        ];
+        let text = grid(Span::Month).render(&schedules);
+        let lines: Vec<_> = text.lines().collect();
+
+        assert_eq!("2024年1月", lines[0]);
+        // 今日は括弧で囲み、重なっている予定には ! を付ける
+        assert!(lines[9].contains("|[10]          |11            |"));
+        assert!(lines[10].contains("|!10:00 定例   | 休暇         | →休暇        |"));
+        assert!(lines[11].contains("|!10:30 面談   |"));
+        assert!(lines[1..]
+            .iter()
+            .all(|line| line.width() == lines[1].width()));
+    }
+
+    #[test]
+    fn test_render_day() {
+        let schedules = vec![
+            Schedule::test(0, "定例", dt(10, 10, 0), dt(10, 11, 0)),
+            Schedule::test(0, "面談", dt(10, 10, 30), dt(10, 11, 30)),
+            Schedule::test(0, "夜の作業", dt(10, 21, 0), dt(10, 22, 0)),
+        ];
+        let text = grid(Span::Day).render(&schedules);
+        let lines: Vec<_> = text.lines().collect();
+
+        assert!(lines[1].contains("[水 1/10]"));
+        assert!(lines[3].starts_with("| 08:00|"));
+        assert!(lines[7].contains("| 10:00|!10:00 定例"));
+        assert!(lines[8].contains("| 10:30|!10:30 面談 +1"));
+        assert!(lines[9].contains("| 11:00|!│"));
+        // 20時より後の予定があれば、その時間まで表示する
+        assert!(lines[29].contains("| 21:00| 21:00 夜の作業"));
+        assert!(lines[30].contains("| 21:30| │"));
+        assert_eq!(32, lines.len());
+    }
+}
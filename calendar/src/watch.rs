@@ -0,0 +1,400 @@
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{IpAddr, TcpStream},
+    path::PathBuf,
+    process::Command,
+    time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+
+use crate::{backend, duration, time_zone, Calendar, MyError};
+
+// webhook の応答を待つ時間
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 通知の時刻になった予定
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Alert {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub calendar: Option<String>,
+    pub id: u64,
+    pub subject: String,
+    pub start: DateTime<Utc>,
+    #[serde(serialize_with = "serialize_before")]
+    pub before: TimeDelta,
+}
+
+fn serialize_before<S: serde::Serializer>(
+    before: &TimeDelta,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&duration::format_duration(*before))
+}
+
+impl Alert {
+    pub fn fire_at(&self) -> DateTime<Utc> {
+        self.start - self.before
+    }
+
+    fn title(&self) -> String {
+        format!(
+            "{}前: {}",
+            duration::format_duration(self.before),
+            self.subject
+        )
+    }
+
+    fn body(&self, zone: Tz) -> String {
+        let start = time_zone::to_local(zone, self.start).format("%Y-%m-%d %H:%M");
+        match &self.calendar {
+            Some(calendar) => format!("{} 開始 ({}, ID: {})", start, calendar, self.id),
+            None => format!("{} 開始 (ID: {})", start, self.id),
+        }
+    }
+}
+
+/// after より後、until 以前に通知の時刻が来る予定を、時刻の順に返す
+pub fn due_alerts(
+    calendar: &Calendar,
+    name: Option<&str>,
+    after: DateTime<Utc>,
+    until: DateTime<Utc>,
+) -> Vec<Alert> {
+    let mut alerts = Vec::new();
+    for schedule in &calendar.schedules {
+        let Some(longest) = schedule.reminders.iter().max() else {
+            continue;
+        };
+        // 通知の時刻が範囲に入る発生は、開始が (after, until + 一番長い通知] にある
+        for occurrence in schedule.occurrences(after, until + *longest + TimeDelta::seconds(1)) {
+            for before in &schedule.reminders {
+                let alert = Alert {
+                    calendar: name.map(str::to_string),
+                    id: schedule.id,
+                    subject: schedule.subject.clone(),
+                    start: occurrence.start_utc(),
+                    before: *before,
+                };
+                if after < alert.fire_at() && alert.fire_at() <= until {
+                    alerts.push(alert);
+                }
+            }
+        }
+    }
+    alerts.sort_by_key(Alert::fire_at);
+    alerts
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SinkKind {
+    /// 標準出力に表示する
+    #[default]
+    Stdout,
+    /// デスクトップ通知のコマンドを実行する
+    Notify,
+    /// 手元の URL に JSON を POST する
+    Webhook,
+}
+
+/// 通知の送り先
+pub trait Sink {
+    fn send(&self, alert: &Alert) -> Result<(), String>;
+}
+
+pub struct StdoutSink {
+    pub zone: Tz,
+}
+
+impl Sink for StdoutSink {
+    fn send(&self, alert: &Alert) -> Result<(), String> {
+        println!("[通知] {} / {}", alert.title(), alert.body(self.zone));
+        Ok(())
+    }
+}
+
+/// notify-send のように件名と本文を引数に取るコマンド
+pub struct CommandSink {
+    pub program: String,
+    pub zone: Tz,
+}
+
+impl Sink for CommandSink {
+    fn send(&self, alert: &Alert) -> Result<(), String> {
+        let status = Command::new(&self.program)
+            .arg(alert.title())
+            .arg(alert.body(self.zone))
+            .status()
+            .map_err(|error| format!("{} を実行できません: {}", self.program, error))?;
+        if !status.success() {
+            return Err(format!("{} が失敗しました ({})", self.program, status));
+        }
+        Ok(())
+    }
+}
+
+/// ループバックアドレスの http の URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebhookUrl {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+/// http://localhost:8080/hook のような手元の URL だけを受け付ける
+pub fn parse_webhook_url(text: &str) -> Result<WebhookUrl, String> {
+    let invalid = |reason: &str| format!("{}: {}", reason, text);
+
+    let rest = text
+        .strip_prefix("http://")
+        .ok_or_else(|| invalid("http:// で始まる URL を指定してください"))?;
+    let (authority, path) = match rest.find('/') {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !host.ends_with(':') => (
+            host,
+            port.parse().map_err(|_| invalid("ポート番号が不正です"))?,
+        ),
+        _ => (authority, 80),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let loopback = host == "localhost"
+        || host
+            .parse::<IpAddr>()
+            .is_ok_and(|address| address.is_loopback());
+    if !loopback {
+        return Err(invalid("localhost 以外には送れません"));
+    }
+    Ok(WebhookUrl {
+        host: host.to_string(),
+        port,
+        path: path.to_string(),
+    })
+}
+
+pub struct WebhookSink {
+    pub url: WebhookUrl,
+}
+
+impl WebhookSink {
+    fn post(&self, body: &str) -> io::Result<u16> {
+        let url = &self.url;
+        let mut stream = TcpStream::connect((url.host.as_str(), url.port))?;
+        stream.set_read_timeout(Some(WEBHOOK_TIMEOUT))?;
+        stream.set_write_timeout(Some(WEBHOOK_TIMEOUT))?;
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            url.path,
+            url.host,
+            url.port,
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes())?;
+
+        // "HTTP/1.1 204 No Content" のステータスコードだけを見る
+        let mut status_line = String::new();
+        BufReader::new(stream).read_line(&mut status_line)?;
+        status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, status_line.trim().to_string())
+            })
+    }
+}
+
+impl Sink for WebhookSink {
+    fn send(&self, alert: &Alert) -> Result<(), String> {
+        let body = serde_json::to_string(alert).map_err(|error| error.to_string())?;
+        match self.post(&body) {
+            Ok(status) if (200..300).contains(&status) => Ok(()),
+            Ok(status) => Err(format!("webhook がステータス {} を返しました", status)),
+            Err(error) => Err(format!("webhook に送れません: {}", error)),
+        }
+    }
+}
+
+/// 監視するカレンダーのファイル
+pub struct Source {
+    pub name: Option<String>,
+    pub path: PathBuf,
+    calendar: Calendar,
+    // 最後に読み込んだときの更新時刻。まだ読み込んでいなければ None
+    modified: Option<Option<SystemTime>>,
+}
+
+impl Source {
+    pub fn new(name: Option<String>, path: PathBuf) -> Self {
+        Self {
+            name,
+            path,
+            calendar: Calendar::default(),
+            modified: None,
+        }
+    }
+
+    // 更新時刻が変わっていたら読み直す。読めなかった場合は前の内容のまま
+    fn reload(&mut self) -> Result<bool, MyError> {
+        let modified = self.path.metadata().and_then(|meta| meta.modified()).ok();
+        if self.modified == Some(modified) {
+            return Ok(false);
+        }
+        self.calendar = backend::open(&self.path).load()?;
+        self.modified = Some(modified);
+        Ok(true)
+    }
+}
+
+/// 終了されるまで、interval ごとにファイルの変更を調べて通知を送る
+pub fn run(sources: &mut [Source], sink: &dyn Sink, interval: TimeDelta) -> Result<(), MyError> {
+    for source in sources.iter_mut() {
+        source.reload()?;
+    }
+    let interval = interval.to_std().unwrap_or(Duration::from_secs(1));
+    eprintln!("通知を待っています (Ctrl-C で終了)");
+
+    let mut checked = Utc::now();
+    loop {
+        std::thread::sleep(interval);
+        for source in sources.iter_mut() {
+            match source.reload() {
+                Ok(true) => eprintln!("{} を読み直しました", source.path.display()),
+                Ok(false) => {}
+                Err(error) => eprintln!("エラー: {}", error),
+            }
+        }
+
+        let now = Utc::now();
+        let mut alerts: Vec<_> = sources
+            .iter()
+            .flat_map(|source| due_alerts(&source.calendar, source.name.as_deref(), checked, now))
+            .collect();
+        alerts.sort_by_key(Alert::fire_at);
+        for alert in alerts {
+            if let Err(error) = sink.send(&alert) {
+                eprintln!("エラー: {}", error);
+            }
+        }
+        checked = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recurrence::{Frequency, Recurrence};
+    use crate::Schedule;
+    use chrono::{NaiveDate, NaiveDateTime};
+    use rstest::rstest;
+    use std::{io::Read, net::TcpListener};
+
+    fn dt(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    fn utc(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        dt(day, hour, minute).and_utc()
+    }
+
+    fn calendar() -> Calendar {
+        Calendar {
+            schedules: vec![
+                Schedule {
+                    recurrence: Some(Recurrence::new(Frequency::Daily)),
+                    reminders: vec![TimeDelta::minutes(10), TimeDelta::days(1)],
+                    ..Schedule::test(0, "定例", dt(1, 10, 0), dt(1, 10, 30))
+                },
+                Schedule::test(1, "通知なし", dt(1, 9, 55), dt(1, 10, 0)),
+            ],
+            next_id: 2,
+        }
+    }
+
+    #[rstest]
+    // 範囲の終わりちょうどの通知は含め、始まりちょうどの通知は含めない
+    #[case(utc(1, 9, 40), utc(1, 9, 50), vec![(1, TimeDelta::minutes(10))])]
+    #[case(utc(1, 9, 50), utc(1, 9, 59), vec![])]
+    // 前日の通知と当日の通知が順に並ぶ
+    #[case(utc(1, 9, 0), utc(2, 9, 55), vec![
+        (1, TimeDelta::minutes(10)),
+        (2, TimeDelta::days(1)),
+        (2, TimeDelta::minutes(10)),
+    ])]
+    fn test_due_alerts(
+        #[case] after: DateTime<Utc>,
+        #[case] until: DateTime<Utc>,
+        #[case] expected: Vec<(u32, TimeDelta)>,
+    ) {
+        let alerts: Vec<_> = due_alerts(&calendar(), None, after, until)
+            .iter()
+            .map(|alert| (alert.start.naive_utc(), alert.before))
+            .collect();
+        let expected: Vec<_> = expected
+            .into_iter()
+            .map(|(day, before)| (dt(day, 10, 0), before))
+            .collect();
+        assert_eq!(expected, alerts);
+    }
+
+    #[rstest]
+    #[case("http://localhost:8080/hook", Some(("localhost", 8080, "/hook")))]
+    #[case("http://127.0.0.1", Some(("127.0.0.1", 80, "/")))]
+    #[case("http://[::1]:9000/a/b", Some(("::1", 9000, "/a/b")))]
+    #[case("https://localhost/hook", None)]
+    #[case("http://example.com/hook", None)]
+    #[case("http://localhost:port/", None)]
+    fn test_parse_webhook_url(#[case] text: &str, #[case] expected: Option<(&str, u16, &str)>) {
+        let expected = expected.map(|(host, port, path)| WebhookUrl {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        });
+        assert_eq!(expected, parse_webhook_url(text).ok());
+    }
+
+    #[test]
+    fn test_webhook_sink() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+            while !request.ends_with(b"}") {
+                let size = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..size]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let sink = WebhookSink {
+            url: parse_webhook_url(&format!("http://localhost:{}/hook", port)).unwrap(),
+        };
+        let alert = Alert {
+            calendar: None,
+            id: 0,
+            subject: "定例".to_string(),
+            start: utc(1, 10, 0),
+            before: TimeDelta::minutes(10),
+        };
+        assert_eq!(Ok(()), sink.send(&alert));
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /hook HTTP/1.1\r\n"));
+        assert!(request.ends_with(
+            r#"{"id":0,"subject":"定例","start":"2024-01-01T10:00:00Z","before":"10m"}"#
+        ));
+    }
+}
@@ -0,0 +1,219 @@
+use chrono::{Datelike, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, Weekday};
+
+use crate::duration;
+
+/// コマンドラインで指定された日付または日時
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum When {
+    /// 日付だけの指定は終日の予定になる
+    Date(NaiveDate),
+    DateTime(NaiveDateTime),
+}
+
+impl When {
+    /// 予定の開始としての日時 (日付ならその日の0時)
+    pub fn start(self) -> NaiveDateTime {
+        match self {
+            When::Date(date) => date.and_hms_opt(0, 0, 0).unwrap(),
+            When::DateTime(date_time) => date_time,
+        }
+    }
+
+    /// 予定の終了としての日時 (日付ならその日を含めるので翌日の0時)
+    pub fn end(self) -> NaiveDateTime {
+        match self {
+            When::Date(date) => When::Date(date).start() + TimeDelta::days(1),
+            When::DateTime(date_time) => date_time,
+        }
+    }
+
+    pub fn is_date(self) -> bool {
+        matches!(self, When::Date(_))
+    }
+}
+
+/// 予定の終了の指定
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum End {
+    At(When),
+    /// 開始からの長さ (+2h や --duration 45m)
+    After(TimeDelta),
+}
+
+const DATE_TIME_FORMATS: [&str; 4] = [
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%d %H:%M",
+];
+
+/// 日時の指定を解釈する。相対的な指定は now (手元のタイムゾーンでの今) を基準にする
+///
+/// - 2024-01-01 / 2024-01-01T10:00 / 2024-01-01 10:00:00
+/// - today / tomorrow / yesterday (今日 / 明日 / 明後日 / 昨日) に時刻を続けてもよい
+/// - mon は今日以降で一番近い月曜日、next mon は明日以降で一番近い月曜日
+/// - 15:00 のように時刻だけなら今日
+/// - +2h / +1d30m は今からの長さ
+pub fn parse_when(text: &str, now: NaiveDateTime) -> Result<When, String> {
+    let text = text.trim();
+    let invalid = || {
+        format!(
+            "日時を解釈できません: {} (例: 2024-01-01T10:00, tomorrow 15:00, next mon 9:30, +2h)",
+            text
+        )
+    };
+
+    if let Some(rest) = text.strip_prefix('+') {
+        let duration = duration::parse_duration(rest).map_err(|_| invalid())?;
+        let date_time = now.checked_add_signed(duration).ok_or_else(invalid)?;
+        return Ok(When::DateTime(date_time));
+    }
+    if let Some(date_time) = DATE_TIME_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+    {
+        return Ok(When::DateTime(date_time));
+    }
+
+    let lower = text.to_lowercase();
+    let mut words: Vec<_> = lower.split_whitespace().collect();
+    let time = words.last().and_then(|word| parse_time(word));
+    if time.is_some() {
+        words.pop();
+    }
+    let date = match words.as_slice() {
+        [] if time.is_some() => now.date(),
+        [] => return Err(invalid()),
+        words => parse_date(words, now.date()).ok_or_else(invalid)?,
+    };
+    Ok(match time {
+        Some(time) => When::DateTime(date.and_time(time)),
+        None => When::Date(date),
+    })
+}
+
+/// 終了の指定を解釈する。+ で始まる場合は開始からの長さ、時刻だけなら start の日の時刻
+pub fn parse_end(text: &str, start: NaiveDateTime, now: NaiveDateTime) -> Result<End, String> {
+    if let Some(time) = parse_time_only(text) {
+        return Ok(End::At(When::DateTime(time_after(start, time))));
+    }
+    match text.trim().strip_prefix('+') {
+        Some(rest) => duration::parse_duration(rest).map(End::After),
+        None => parse_when(text, now).map(End::At),
+    }
+}
+
+/// 15:00 のように時刻だけの指定ならその時刻
+pub fn parse_time_only(text: &str) -> Option<NaiveTime> {
+    parse_time(text.trim())
+}
+
+/// 終了の時刻だけが指定されたときの終了日時。start の日のその時刻で、start より後になるよう翌日に送る
+pub fn time_after(start: NaiveDateTime, time: NaiveTime) -> NaiveDateTime {
+    let end = start.date().and_time(time);
+    if end <= start {
+        end + TimeDelta::days(1)
+    } else {
+        end
+    }
+}
+
+fn parse_time(text: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(text, "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(text, "%H:%M:%S"))
+        .ok()
+}
+
+fn parse_date(words: &[&str], today: NaiveDate) -> Option<NaiveDate> {
+    let days = |n| Some(today + TimeDelta::days(n));
+    match words {
+        [date] if date.contains('-') => NaiveDate::parse_from_str(date, "%Y-%m-%d").ok(),
+        ["today" | "今日"] => days(0),
+        ["tomorrow" | "明日"] => days(1),
+        ["明後日"] => days(2),
+        ["yesterday" | "昨日"] => days(-1),
+        [day] => Some(next_weekday(today, day.parse().ok()?)),
+        ["next", day] => Some(next_weekday(today + TimeDelta::days(1), day.parse().ok()?)),
+        _ => None,
+    }
+}
+
+// from 以降で最初の weekday の日
+fn next_weekday(from: NaiveDate, weekday: Weekday) -> NaiveDate {
+    let ahead = (7 + weekday.num_days_from_monday() - from.weekday().num_days_from_monday()) % 7;
+    from + TimeDelta::days(ahead as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 1, day).unwrap()
+    }
+
+    fn dt(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        date(day).and_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    // 2024年1月3日 (水) 12:00 を今とする
+    fn now() -> NaiveDateTime {
+        dt(3, 12, 0)
+    }
+
+    #[rstest]
+    #[case("2024-01-02", Some(When::Date(date(2))))]
+    #[case("2024-01-02T10:30:00", Some(When::DateTime(dt(2, 10, 30))))]
+    #[case("2024-01-02T10:30", Some(When::DateTime(dt(2, 10, 30))))]
+    #[case("2024-01-02 10:30", Some(When::DateTime(dt(2, 10, 30))))]
+    #[case("2024-01-02 9:30", Some(When::DateTime(dt(2, 9, 30))))]
+    #[case("tomorrow 15:00", Some(When::DateTime(dt(4, 15, 0))))]
+    #[case("Tomorrow", Some(When::Date(date(4))))]
+    #[case("明日 9:00", Some(When::DateTime(dt(4, 9, 0))))]
+    #[case("yesterday", Some(When::Date(date(2))))]
+    #[case("today 18:30", Some(When::DateTime(dt(3, 18, 30))))]
+    #[case("15:00", Some(When::DateTime(dt(3, 15, 0))))]
+    #[case("mon 9:30", Some(When::DateTime(dt(8, 9, 30))))]
+    #[case("wed", Some(When::Date(date(3))))]
+    #[case("next wed", Some(When::Date(date(10))))]
+    #[case("next monday 9:30", Some(When::DateTime(dt(8, 9, 30))))]
+    #[case("+2h", Some(When::DateTime(dt(3, 14, 0))))]
+    #[case("+1d30m", Some(When::DateTime(dt(4, 12, 30))))]
+    #[case("2024-01-32", None)]
+    #[case("next", None)]
+    #[case("someday 10:00", None)]
+    #[case("+2", None)]
+    // 日時として表せない先
+    #[case("+99999999999999d", None)]
+    fn test_parse_when(#[case] text: &str, #[case] expected: Option<When>) {
+        assert_eq!(expected, parse_when(text, now()).ok());
+    }
+
+    #[rstest]
+    #[case("+45m", dt(3, 15, 0), Some(End::After(TimeDelta::minutes(45))))]
+    // 時刻だけの終了は今日ではなく開始の日の時刻
+    #[case("16:00", dt(4, 15, 0), Some(End::At(When::DateTime(dt(4, 16, 0)))))]
+    #[case(" 16:00 ", dt(3, 9, 0), Some(End::At(When::DateTime(dt(3, 16, 0)))))]
+    // 開始より前の時刻なら翌日
+    #[case("1:00", dt(3, 23, 0), Some(End::At(When::DateTime(dt(4, 1, 0)))))]
+    #[case("15:00", dt(3, 15, 0), Some(End::At(When::DateTime(dt(4, 15, 0)))))]
+    #[case(
+        "tomorrow 16:00",
+        dt(3, 15, 0),
+        Some(End::At(When::DateTime(dt(4, 16, 0))))
+    )]
+    #[case("+x", dt(3, 15, 0), None)]
+    fn test_parse_end(
+        #[case] text: &str,
+        #[case] start: NaiveDateTime,
+        #[case] expected: Option<End>,
+    ) {
+        assert_eq!(expected, parse_end(text, start, now()).ok());
+    }
+
+    #[test]
+    fn test_date_end_includes_the_day() {
+        assert_eq!(dt(3, 0, 0), When::Date(date(2)).end());
+    }
+}
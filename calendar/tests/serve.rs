@@ -0,0 +1,228 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+};
+
+use serde_json::{json, Value};
+
+const NOW: &str = "2024-01-03T03:00:00Z";
+
+// テストの終わりにサーバーを止め、データのディレクトリを消す
+struct Server {
+    child: Child,
+    address: String,
+    dir: PathBuf,
+}
+
+impl Server {
+    fn start(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("calendar-serve-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut child = calendar(&dir)
+            .args(["serve", "--port", "0"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut line = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        let address = line
+            .trim()
+            .strip_prefix("http://")
+            .and_then(|rest| rest.split('/').next())
+            .unwrap()
+            .to_string();
+        Self {
+            child,
+            address,
+            dir,
+        }
+    }
+
+    fn request(&self, method: &str, target: &str, body: Option<Value>) -> (u16, Option<Value>) {
+        let body = body.map(|body| body.to_string()).unwrap_or_default();
+        let mut stream = TcpStream::connect(&self.address).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            target,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        let body = (!body.is_empty()).then(|| serde_json::from_str(body).unwrap());
+        (status, body)
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn calendar(dir: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_calendar"));
+    command
+        .env("CALENDAR_DIR", dir)
+        .env("CALENDAR_NOW", NOW)
+        .env("TZ", "UTC");
+    command
+}
+
+fn subjects(body: &Value) -> Vec<&str> {
+    body.as_array()
+        .unwrap()
+        .iter()
+        .map(|row| row["subject"].as_str().unwrap())
+        .collect()
+}
+
+#[test]
+fn test_add_list_edit_delete() {
+    let server = Server::start("crud");
+
+    let (status, body) = server.request(
+        "POST",
+        "/schedules",
+        Some(json!({
+            "subject": "会議",
+            "start": "2024-01-10T10:00",
+            "duration": "1h",
+            "tags": ["仕事"],
+        })),
+    );
+    assert_eq!(201, status);
+    assert_eq!(Some(json!({"id": 0})), body);
+
+    let (status, body) = server.request(
+        "POST",
+        "/schedules",
+        Some(json!({"subject": "昼食", "start": "2024-01-11T12:00", "end": "2024-01-11T13:00"})),
+    );
+    assert_eq!(201, status);
+    assert_eq!(Some(json!({"id": 1})), body);
+
+    // 同じ検証を通るので、重なる予定は CLI と同じく断られる
+    let (status, body) = server.request(
+        "POST",
+        "/schedules",
+        Some(json!({"subject": "面談", "start": "2024-01-10T10:30", "duration": "1h"})),
+    );
+    assert_eq!(409, status);
+    assert_eq!(json!([0]), body.unwrap()["conflicts"]);
+
+    let (status, body) = server.request(
+        "POST",
+        "/schedules",
+        Some(json!({
+            "subject": "面談",
+            "start": "2024-01-10T10:30",
+            "duration": "1h",
+            "allow_overlap": true,
+        })),
+    );
+    assert_eq!(201, status);
+    assert_eq!(Some(json!({"id": 2})), body);
+
+    let (status, body) = server.request(
+        "GET",
+        "/schedules?from=2024-01-10&to=2024-01-11T00%3A00%3A00%2B00%3A00",
+        None,
+    );
+    assert_eq!(200, status);
+    assert_eq!(vec!["会議", "面談"], subjects(body.as_ref().unwrap()));
+    assert_eq!(json!(["仕事"]), body.unwrap()[0]["tags"]);
+
+    let (status, _) = server.request(
+        "PATCH",
+        "/schedules/1",
+        Some(json!({"subject": "ランチ", "shift": "1d"})),
+    );
+    assert_eq!(200, status);
+    let (status, _) = server.request(
+        "PATCH",
+        "/schedules/1",
+        Some(json!({"start": "2024-01-12T15:00", "end": "2024-01-12T14:00"})),
+    );
+    assert_eq!(400, status);
+    let (status, _) = server.request("PATCH", "/schedules/9", Some(json!({"subject": "x"})));
+    assert_eq!(404, status);
+
+    let (status, body) = server.request("DELETE", "/schedules/2", None);
+    assert_eq!(204, status);
+    assert_eq!(None, body);
+    let (status, _) = server.request("DELETE", "/schedules/2", None);
+    assert_eq!(404, status);
+
+    // サーバーで変えた内容は CLI からも見える
+    let output = calendar(&server.dir)
+        .args(["list", "--format", "json"])
+        .output()
+        .unwrap();
+    let body: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(output.status.success());
+    assert_eq!(vec!["会議", "ランチ"], subjects(&body));
+    assert_eq!("2024-01-12T12:00:00+00:00", body[1]["start"]);
+}
+
+#[test]
+fn test_bad_requests() {
+    let server = Server::start("bad");
+
+    let (status, body) = server.request("POST", "/schedules", Some(json!({"start": "x"})));
+    assert_eq!(400, status);
+    assert!(body.unwrap()["error"].is_string());
+    let (status, _) = server.request(
+        "POST",
+        "/schedules",
+        Some(json!({"subject": "会議", "start": "someday"})),
+    );
+    assert_eq!(400, status);
+    let (status, _) = server.request("GET", "/schedules?tz=Mars%2FBase", None);
+    assert_eq!(400, status);
+    let (status, _) = server.request("PUT", "/schedules/0", None);
+    assert_eq!(405, status);
+    let (status, _) = server.request("GET", "/calendars", None);
+    assert_eq!(404, status);
+    let (status, _) = server.request("DELETE", "/schedules/abc", None);
+    assert_eq!(400, status);
+}
+
+#[test]
+fn test_oversized_values_do_not_stop_the_server() {
+    let server = Server::start("oversized");
+
+    for body in [
+        json!({"subject": "会議", "start": "2024-01-10T10:00", "duration": "99999999999999d"}),
+        json!({"subject": "会議", "start": "+99999999999999d", "duration": "1h"}),
+        json!({"subject": "会議", "start": "2024-01-10T10:00", "end": "+99999999999999d"}),
+    ] {
+        let (status, body) = server.request("POST", "/schedules", Some(body));
+        assert_eq!(400, status);
+        assert!(body.unwrap()["error"].is_string());
+    }
+    let (status, _) = server.request(
+        "PATCH",
+        "/schedules/0",
+        Some(json!({"shift": "-99999999999999d"})),
+    );
+    assert_eq!(400, status);
+
+    // 続くリクエストにも応える
+    let (status, body) = server.request("GET", "/schedules", None);
+    assert_eq!(200, status);
+    assert_eq!(Some(json!([])), body);
+}